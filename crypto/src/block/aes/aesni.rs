@@ -0,0 +1,394 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! `Aes128`/`Aes192`/`Aes256`: the public, key-size-generic front ends for this crate's AES
+//! implementations. Each probes `is_x86_feature_detected!("aes")` once, at construction, and
+//! stores whichever backend it found - the hardware `aesenc`/`aesenclast`/`aesdec`/`aesdeclast`
+//! path on `x86_64` CPUs that support it, or the constant-time `safe::Bs8State` bitsliced path
+//! from `super::safe` everywhere else. `encrypt_block`/`decrypt_block` dispatch to whichever
+//! backend was selected; callers see a single `BlockEncrypt`/`BlockDecrypt` impl regardless.
+
+use typenum::consts::U16;
+
+use block::{BlockDecrypt, BlockEncrypt};
+
+use super::safe;
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod hw {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    // Derives the 11 AES-128 round keys with `aeskeygenassist`/`aesenc`-style key expansion: each
+    // new word is the previous one XORed with a rotated, sub-byte'd, rcon'd copy of itself,
+    // broadcast across the 128-bit lane with shuffles, following the standard Intel AES-NI
+    // key-schedule pattern.
+    pub unsafe fn expand_key_128(key: &[u8; 16]) -> [__m128i; 11] {
+        unsafe fn expand_round(prev: __m128i, keygened: __m128i) -> __m128i {
+            let keygened = _mm_shuffle_epi32(keygened, 0xff);
+            let mut prev = prev;
+            prev = _mm_xor_si128(prev, _mm_slli_si128(prev, 4));
+            prev = _mm_xor_si128(prev, _mm_slli_si128(prev, 4));
+            prev = _mm_xor_si128(prev, _mm_slli_si128(prev, 4));
+            _mm_xor_si128(prev, keygened)
+        }
+
+        macro_rules! round {
+            ($prev:expr, $rcon:expr) => {{
+                let assisted = _mm_aeskeygenassist_si128($prev, $rcon);
+                expand_round($prev, assisted)
+            }}
+        }
+
+        let k0 = _mm_loadu_si128(key.as_ptr() as *const __m128i);
+        let k1 = round!(k0, 0x01);
+        let k2 = round!(k1, 0x02);
+        let k3 = round!(k2, 0x04);
+        let k4 = round!(k3, 0x08);
+        let k5 = round!(k4, 0x10);
+        let k6 = round!(k5, 0x20);
+        let k7 = round!(k6, 0x40);
+        let k8 = round!(k7, 0x80);
+        let k9 = round!(k8, 0x1b);
+        let k10 = round!(k9, 0x36);
+
+        [k0, k1, k2, k3, k4, k5, k6, k7, k8, k9, k10]
+    }
+
+    // AES-256's key schedule alternates between two `aeskeygenassist` patterns: odd round keys
+    // use the usual rotate+sub_word+rcon assist against the key two back, even round keys (other
+    // than the first two, which are just the raw key material) use a zero-rcon assist (sub_word
+    // only, no rotate) against the key immediately before them.
+    pub unsafe fn expand_key_256(key: &[u8; 32]) -> [__m128i; 15] {
+        unsafe fn assist_1(prev: __m128i, keygened: __m128i) -> __m128i {
+            let keygened = _mm_shuffle_epi32(keygened, 0xff);
+            let mut prev = prev;
+            prev = _mm_xor_si128(prev, _mm_slli_si128(prev, 4));
+            prev = _mm_xor_si128(prev, _mm_slli_si128(prev, 4));
+            prev = _mm_xor_si128(prev, _mm_slli_si128(prev, 4));
+            _mm_xor_si128(prev, keygened)
+        }
+
+        unsafe fn assist_2(prev: __m128i, two_back: __m128i) -> __m128i {
+            let keygened = _mm_aeskeygenassist_si128(prev, 0x00);
+            let keygened = _mm_shuffle_epi32(keygened, 0xaa);
+            let mut two_back = two_back;
+            two_back = _mm_xor_si128(two_back, _mm_slli_si128(two_back, 4));
+            two_back = _mm_xor_si128(two_back, _mm_slli_si128(two_back, 4));
+            two_back = _mm_xor_si128(two_back, _mm_slli_si128(two_back, 4));
+            _mm_xor_si128(two_back, keygened)
+        }
+
+        let k0 = _mm_loadu_si128(key[0..16].as_ptr() as *const __m128i);
+        let k1 = _mm_loadu_si128(key[16..32].as_ptr() as *const __m128i);
+
+        let k2 = assist_1(k0, _mm_aeskeygenassist_si128(k1, 0x01));
+        let k3 = assist_2(k2, k1);
+        let k4 = assist_1(k2, _mm_aeskeygenassist_si128(k3, 0x02));
+        let k5 = assist_2(k4, k3);
+        let k6 = assist_1(k4, _mm_aeskeygenassist_si128(k5, 0x04));
+        let k7 = assist_2(k6, k5);
+        let k8 = assist_1(k6, _mm_aeskeygenassist_si128(k7, 0x08));
+        let k9 = assist_2(k8, k7);
+        let k10 = assist_1(k8, _mm_aeskeygenassist_si128(k9, 0x10));
+        let k11 = assist_2(k10, k9);
+        let k12 = assist_1(k10, _mm_aeskeygenassist_si128(k11, 0x20));
+        let k13 = assist_2(k12, k11);
+        let k14 = assist_1(k12, _mm_aeskeygenassist_si128(k13, 0x40));
+
+        [k0, k1, k2, k3, k4, k5, k6, k7, k8, k9, k10, k11, k12, k13, k14]
+    }
+
+    // AES-192's 192-bit key doesn't split evenly into 128-bit `aeskeygenassist` blocks the way
+    // AES-128/256 do, so its round keys are derived with the existing scalar `create_round_keys`
+    // schedule from `safe` instead and simply loaded into `__m128i`s here - the throughput win
+    // from running `aesenc`/`aesdec` in hardware doesn't depend on how the (one-time, cheap)
+    // key schedule itself was computed.
+    pub fn load_round_keys_192(words: &[[u32; 4]; 13]) -> [__m128i; 13] {
+        let mut out = [unsafe { _mm_setzero_si128() }; 13];
+        for (rk, word) in out.iter_mut().zip(words.iter()) {
+            *rk = unsafe { _mm_set_epi32(word[3] as i32, word[2] as i32, word[1] as i32, word[0] as i32) };
+        }
+        out
+    }
+
+    pub unsafe fn encrypt_block(round_keys: &[__m128i], input: &[u8]) -> [u8; 16] {
+        let last = round_keys.len() - 1;
+
+        let mut state = _mm_xor_si128(_mm_loadu_si128(input.as_ptr() as *const __m128i),
+                                       round_keys[0]);
+        for rk in &round_keys[1..last] {
+            state = _mm_aesenc_si128(state, *rk);
+        }
+        state = _mm_aesenclast_si128(state, round_keys[last]);
+
+        let mut out = [0u8; 16];
+        _mm_storeu_si128(out.as_mut_ptr() as *mut __m128i, state);
+        out
+    }
+
+    pub unsafe fn decrypt_block(round_keys: &[__m128i], input: &[u8]) -> [u8; 16] {
+        let last = round_keys.len() - 1;
+
+        let mut state = _mm_xor_si128(_mm_loadu_si128(input.as_ptr() as *const __m128i),
+                                       round_keys[last]);
+        for rk in round_keys[1..last].iter().rev() {
+            state = _mm_aesdec_si128(state, _mm_aesimc_si128(*rk));
+        }
+        state = _mm_aesdeclast_si128(state, round_keys[0]);
+
+        let mut out = [0u8; 16];
+        _mm_storeu_si128(out.as_mut_ptr() as *mut __m128i, state);
+        out
+    }
+}
+
+macro_rules! define_aesni_front_end {
+    ($name:ident, $safe_enc:ident, $safe_dec:ident, $rounds:expr, $key_size:expr, $expand:path) => {
+        enum Backend {
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            Ni([::std::arch::x86_64::__m128i; $rounds + 1]),
+            Safe(safe::$safe_enc, safe::$safe_dec),
+        }
+
+        /// A `BlockEncrypt`/`BlockDecrypt` front end that dispatches to hardware AES-NI when the
+        /// running CPU supports it, falling back to the constant-time bitsliced implementation
+        /// from `safe` otherwise.
+        pub struct $name {
+            backend: Backend,
+        }
+
+        impl $name {
+            pub fn new(key: &[u8]) -> $name {
+                assert_eq!(key.len(), $key_size);
+
+                #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                {
+                    if is_x86_feature_detected!("aes") {
+                        let mut fixed = [0u8; $key_size];
+                        fixed.copy_from_slice(key);
+                        let round_keys = unsafe { $expand(&fixed) };
+                        return $name { backend: Backend::Ni(round_keys) };
+                    }
+                }
+
+                $name {
+                    backend: Backend::Safe(safe::$safe_enc::new(key), safe::$safe_dec::new(key)),
+                }
+            }
+        }
+
+        impl BlockEncrypt for $name {
+            type BlockSize = U16;
+
+            fn encrypt_block<I: AsRef<[u8]>, O: AsMut<[u8]>>(&self, input: I, mut output: O) {
+                match self.backend {
+                    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                    Backend::Ni(ref round_keys) => {
+                        let block = unsafe { hw::encrypt_block(round_keys, input.as_ref()) };
+                        output.as_mut().copy_from_slice(&block);
+                    }
+                    Backend::Safe(ref enc, _) => enc.encrypt_block(input, output),
+                }
+            }
+        }
+
+        impl BlockDecrypt for $name {
+            type BlockSize = U16;
+
+            fn decrypt_block<I: AsRef<[u8]>, O: AsMut<[u8]>>(&self, input: I, mut output: O) {
+                match self.backend {
+                    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                    Backend::Ni(ref round_keys) => {
+                        let block = unsafe { hw::decrypt_block(round_keys, input.as_ref()) };
+                        output.as_mut().copy_from_slice(&block);
+                    }
+                    Backend::Safe(_, ref dec) => dec.decrypt_block(input, output),
+                }
+            }
+        }
+    }
+}
+
+// No hardware-accelerated backend is available off x86/x86_64: these always run the bitsliced
+// `safe` implementation, with the same public API as the dispatching front ends above.
+macro_rules! define_aesni_fallback {
+    ($name:ident, $safe_enc:ident, $safe_dec:ident) => {
+        pub struct $name {
+            enc: safe::$safe_enc,
+            dec: safe::$safe_dec,
+        }
+
+        impl $name {
+            pub fn new(key: &[u8]) -> $name {
+                $name {
+                    enc: safe::$safe_enc::new(key),
+                    dec: safe::$safe_dec::new(key),
+                }
+            }
+        }
+
+        impl BlockEncrypt for $name {
+            type BlockSize = U16;
+
+            fn encrypt_block<I: AsRef<[u8]>, O: AsMut<[u8]>>(&self, input: I, output: O) {
+                self.enc.encrypt_block(input, output)
+            }
+        }
+
+        impl BlockDecrypt for $name {
+            type BlockSize = U16;
+
+            fn decrypt_block<I: AsRef<[u8]>, O: AsMut<[u8]>>(&self, input: I, output: O) {
+                self.dec.decrypt_block(input, output)
+            }
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+define_aesni_front_end!(Aes128,
+                        AesSafe128Encryptor,
+                        AesSafe128Decryptor,
+                        10,
+                        16,
+                        hw::expand_key_128);
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+define_aesni_fallback!(Aes128, AesSafe128Encryptor, AesSafe128Decryptor);
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+define_aesni_front_end!(Aes256,
+                        AesSafe256Encryptor,
+                        AesSafe256Decryptor,
+                        14,
+                        32,
+                        hw::expand_key_256);
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+define_aesni_fallback!(Aes256, AesSafe256Encryptor, AesSafe256Decryptor);
+
+/// AES-192's front end is hand-written rather than going through `define_aesni_front_end!`: its
+/// hardware-accelerated round keys come from the scalar `safe::create_round_keys` schedule (see
+/// `hw::load_round_keys_192`) rather than from `aeskeygenassist`.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub struct Aes192 {
+    backend: Aes192Backend,
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+enum Aes192Backend {
+    Ni([::std::arch::x86_64::__m128i; 13]),
+    Safe(safe::AesSafe192Encryptor, safe::AesSafe192Decryptor),
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl Aes192 {
+    pub fn new(key: &[u8]) -> Aes192 {
+        assert_eq!(key.len(), 24);
+
+        if is_x86_feature_detected!("aes") {
+            let words = safe::encryption_round_keys_192(key);
+            return Aes192 { backend: Aes192Backend::Ni(hw::load_round_keys_192(&words)) };
+        }
+
+        Aes192 {
+            backend: Aes192Backend::Safe(safe::AesSafe192Encryptor::new(key),
+                                          safe::AesSafe192Decryptor::new(key)),
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl BlockEncrypt for Aes192 {
+    type BlockSize = U16;
+
+    fn encrypt_block<I: AsRef<[u8]>, O: AsMut<[u8]>>(&self, input: I, mut output: O) {
+        match self.backend {
+            Aes192Backend::Ni(ref round_keys) => {
+                let block = unsafe { hw::encrypt_block(round_keys, input.as_ref()) };
+                output.as_mut().copy_from_slice(&block);
+            }
+            Aes192Backend::Safe(ref enc, _) => enc.encrypt_block(input, output),
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+impl BlockDecrypt for Aes192 {
+    type BlockSize = U16;
+
+    fn decrypt_block<I: AsRef<[u8]>, O: AsMut<[u8]>>(&self, input: I, mut output: O) {
+        match self.backend {
+            Aes192Backend::Ni(ref round_keys) => {
+                let block = unsafe { hw::decrypt_block(round_keys, input.as_ref()) };
+                output.as_mut().copy_from_slice(&block);
+            }
+            Aes192Backend::Safe(_, ref dec) => dec.decrypt_block(input, output),
+        }
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+define_aesni_fallback!(Aes192, AesSafe192Encryptor, AesSafe192Decryptor);
+
+#[cfg(test)]
+mod tests {
+    use super::{Aes128, Aes192, Aes256};
+    use super::super::test_vectors::{KEY_128, KEY_192, KEY_256, PLAINTEXT, CIPHERTEXT_128,
+                                      CIPHERTEXT_192, CIPHERTEXT_256};
+    use block::{BlockDecrypt, BlockEncrypt};
+
+    #[test]
+    fn matches_fips_197_aes_128() {
+        let cipher = Aes128::new(&KEY_128);
+        let mut out = [0u8; 16];
+        cipher.encrypt_block(&PLAINTEXT[..], &mut out[..]);
+        assert_eq!(out, CIPHERTEXT_128);
+
+        let mut back = [0u8; 16];
+        cipher.decrypt_block(&out[..], &mut back[..]);
+        assert_eq!(back, PLAINTEXT);
+    }
+
+    #[test]
+    fn matches_fips_197_aes_192() {
+        let cipher = Aes192::new(&KEY_192);
+        let mut out = [0u8; 16];
+        cipher.encrypt_block(&PLAINTEXT[..], &mut out[..]);
+        assert_eq!(out, CIPHERTEXT_192);
+
+        let mut back = [0u8; 16];
+        cipher.decrypt_block(&out[..], &mut back[..]);
+        assert_eq!(back, PLAINTEXT);
+    }
+
+    #[test]
+    fn matches_fips_197_aes_256() {
+        let cipher = Aes256::new(&KEY_256);
+        let mut out = [0u8; 16];
+        cipher.encrypt_block(&PLAINTEXT[..], &mut out[..]);
+        assert_eq!(out, CIPHERTEXT_256);
+
+        let mut back = [0u8; 16];
+        cipher.decrypt_block(&out[..], &mut back[..]);
+        assert_eq!(back, PLAINTEXT);
+    }
+
+    #[test]
+    fn round_trips_arbitrary_blocks() {
+        let key: [u8; 16] = *b"0123456789abcdef";
+        let cipher = Aes128::new(&key);
+        let plaintext: [u8; 16] = *b"abcdefghijklmnop";
+
+        let mut ciphertext = [0u8; 16];
+        cipher.encrypt_block(&plaintext[..], &mut ciphertext[..]);
+        let mut recovered = [0u8; 16];
+        cipher.decrypt_block(&ciphertext[..], &mut recovered[..]);
+
+        assert_eq!(recovered, plaintext);
+    }
+}
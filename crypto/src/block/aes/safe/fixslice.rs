@@ -0,0 +1,346 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A "fixsliced" backend for AES-128/256, built on the same `Gf8<u16>` bit-sliced state as the
+//! rest of this module, but which never calls `shift_rows`/`inv_shift_rows` in its round loop.
+//!
+//! `shift_rows` has order 4 (applying it four times is the identity), so instead of permuting the
+//! state every round, we fold that permutation into the key schedule and into four periodic
+//! variants of `mix_columns`, chosen by `round % 4`. Concretely: if a round's accumulated,
+//! not-yet-applied `shift_rows` offset is `class = round % 4`, then
+//! `mix_columns(shift_rows^class(x))` equals `shift_rows^class(mix_columns_class(x))` for a fixed
+//! linear operator `mix_columns_class` derived below, and `add_round_key` commutes with any
+//! permutation applied consistently to both operands. So a round key for round `r` is
+//! pre-rotated by `shift_rows^-r` once, at key-schedule time, and the round loop runs `sub_bytes`,
+//! `mix_columns_class(round % 4)`, `add_round_key` with no explicit row permutation at all. The
+//! only place a real `shift_rows` still runs is a fixed two-fold correction applied once to the
+//! finished state, to undo the net permutation the last round key's rotation didn't already
+//! cancel (both AES-128's 10 rounds and AES-256's 14 rounds are `≡ 2 (mod 4)`).
+//!
+//! The same technique applies symmetrically to decryption: `inv_shift_rows` is also order 4 (it's
+//! just `shift_rows` run backwards), so `AesFixslice128Decryptor`/`AesFixslice256Decryptor` below
+//! fold it into a set of pre-rotated decryption round keys and four periodic `inv_mix_columns`
+//! variants the same way, with the rotation direction reversed to match `inv_shift_rows` being the
+//! inverse permutation.
+
+use super::{AesBitValueOps, AesOps, Gf8, KeyType, bit_slice_1x16_with_u16, bit_slice_4x4_with_u16,
+            create_round_keys, un_bit_slice_1x16_with_u16};
+use block::{BlockDecrypt, BlockEncrypt};
+use typenum::consts::U16;
+
+// Rotates each of a plane's four row-nibbles left by one bit position, independently of the
+// others. This is the building block `rotate_cols` below uses; it has nothing to do with
+// `AesBitValueOps::shift_row`, which rotates by a row-*dependent* amount instead.
+fn nibble_rotate_left_1(x: u16) -> u16 {
+    let wrapped = x & 0x8888;
+    ((x << 1) & 0xeeee) | (wrapped >> 3)
+}
+
+// Rotates every row-nibble's bits left by `amount` positions, the same amount in every row. This
+// is exactly what conjugating a bit-plane by `shift_row`/`inv_shift_row` some number of times
+// reduces to once it's distributed across `mix_columns`' `ror1`/`ror2`/`ror3` terms - see
+// `mix_columns_class`.
+fn rotate_cols(x: u16, amount: u32) -> u16 {
+    let mut x = x;
+    for _ in 0..(amount % 4) {
+        x = nibble_rotate_left_1(x);
+    }
+    x
+}
+
+// The periodic MixColumns variant for a round whose accumulated ShiftRows offset is `class`
+// (`round % 4`). `class == 0` is exactly `Gf8::mix_columns`; every other class conjugates each
+// `rorN()` term of that formula by `rotate_cols(class * n)`.
+fn mix_columns_class(state: Gf8<u16>, class: u32) -> Gf8<u16> {
+    let Gf8(x0, x1, x2, x3, x4, x5, x6, x7) = state;
+
+    let r = |x: u16, n: u32| -> u16 {
+        let x = rotate_cols(x, class * n);
+        match n % 4 {
+            0 => x,
+            1 => x.rotate_right(4),
+            2 => x.rotate_right(8),
+            _ => x.rotate_right(12),
+        }
+    };
+
+    let x0out = x7 ^ r(x7, 1) ^ r(x0, 1) ^ r(x0, 2) ^ r(x0, 3);
+    let x1out = x0 ^ r(x0, 1) ^ x7 ^ r(x7, 1) ^ r(x1, 1) ^ r(x1, 2) ^ r(x1, 3);
+    let x2out = x1 ^ r(x1, 1) ^ r(x2, 1) ^ r(x2, 2) ^ r(x2, 3);
+    let x3out = x2 ^ r(x2, 1) ^ x7 ^ r(x7, 1) ^ r(x3, 1) ^ r(x3, 2) ^ r(x3, 3);
+    let x4out = x3 ^ r(x3, 1) ^ x7 ^ r(x7, 1) ^ r(x4, 1) ^ r(x4, 2) ^ r(x4, 3);
+    let x5out = x4 ^ r(x4, 1) ^ r(x5, 1) ^ r(x5, 2) ^ r(x5, 3);
+    let x6out = x5 ^ r(x5, 1) ^ r(x6, 1) ^ r(x6, 2) ^ r(x6, 3);
+    let x7out = x6 ^ r(x6, 1) ^ r(x7, 1) ^ r(x7, 2) ^ r(x7, 3);
+
+    Gf8(x0out, x1out, x2out, x3out, x4out, x5out, x6out, x7out)
+}
+
+// The periodic InvMixColumns variant for a decryption round whose accumulated, not-yet-applied
+// InvShiftRows offset is `class`. Since `inv_shift_rows` is `shift_rows^-1`, conjugating by
+// `inv_shift_rows^class` is the same as conjugating by `shift_rows^(4 - class) % 4`, so this reuses
+// `rotate_cols` with that flipped exponent against each `rorN()` term of `Gf8::inv_mix_columns`'s
+// formula; `class == 0` again reduces exactly to the unmodified formula.
+fn inv_mix_columns_class(state: Gf8<u16>, class: u32) -> Gf8<u16> {
+    let Gf8(x0, x1, x2, x3, x4, x5, x6, x7) = state;
+    let k = (4 - class) % 4;
+
+    let r = |x: u16, n: u32| -> u16 {
+        let x = rotate_cols(x, k * n);
+        match n % 4 {
+            0 => x,
+            1 => x.rotate_right(4),
+            2 => x.rotate_right(8),
+            _ => x.rotate_right(12),
+        }
+    };
+
+    let x0out = x5 ^ x6 ^ x7 ^ r(x5 ^ x7 ^ x0, 1) ^ r(x0 ^ x5 ^ x6, 2) ^ r(x5 ^ x0, 3);
+    let x1out = x5 ^ x0 ^ r(x6 ^ x5 ^ x0 ^ x7 ^ x1, 1) ^ r(x1 ^ x7 ^ x5, 2) ^ r(x6 ^ x5 ^ x1, 3);
+    let x2out = x6 ^ x0 ^ x1 ^ r(x7 ^ x6 ^ x1 ^ x2, 1) ^ r(x0 ^ x2 ^ x6, 2) ^ r(x7 ^ x6 ^ x2, 3);
+    let x3out = x0 ^ x5 ^ x1 ^ x6 ^ x2 ^ r(x0 ^ x5 ^ x2 ^ x3, 1) ^
+                r(x0 ^ x1 ^ x3 ^ x5 ^ x6 ^ x7, 2) ^ r(x0 ^ x5 ^ x7 ^ x3, 3);
+    let x4out = x1 ^ x5 ^ x2 ^ x3 ^ r(x1 ^ x6 ^ x5 ^ x3 ^ x7 ^ x4, 1) ^
+                r(x1 ^ x2 ^ x4 ^ x5 ^ x7, 2) ^ r(x1 ^ x5 ^ x6 ^ x4, 3);
+    let x5out = x2 ^ x6 ^ x3 ^ x4 ^ r(x2 ^ x7 ^ x6 ^ x4 ^ x5, 1) ^ r(x2 ^ x3 ^ x5 ^ x6, 2) ^
+                r(x2 ^ x6 ^ x7 ^ x5, 3);
+    let x6out = x3 ^ x7 ^ x4 ^ x5 ^ r(x3 ^ x7 ^ x5 ^ x6, 1) ^ r(x3 ^ x4 ^ x6 ^ x7, 2) ^
+                r(x3 ^ x7 ^ x6, 3);
+    let x7out = x4 ^ x5 ^ x6 ^ r(x4 ^ x6 ^ x7, 1) ^ r(x4 ^ x5 ^ x7, 2) ^ r(x4 ^ x7, 3);
+
+    Gf8(x0out, x1out, x2out, x3out, x4out, x5out, x6out, x7out)
+}
+
+// Builds the bit-sliced, fixsliced round-key schedule: the ordinary encryption round keys from
+// `create_round_keys`, each pre-rotated by `shift_rows^-round` so the round loop never has to
+// apply `shift_rows` itself.
+fn fixslice_round_keys(key: &[u8], rounds: usize) -> Vec<Gf8<u16>> {
+    let mut tmp = vec![[0u32; 4]; rounds + 1];
+    create_round_keys(key, KeyType::Encryption, &mut tmp);
+
+    tmp.iter()
+        .enumerate()
+        .map(|(round, rk)| {
+            let Gf8(x0, x1, x2, x3, x4, x5, x6, x7) =
+                bit_slice_4x4_with_u16(rk[0], rk[1], rk[2], rk[3]);
+            let mut sliced = Gf8(x0, x1, x2, x3, x4, x5, x6, x7);
+            for _ in 0..(round % 4) {
+                let Gf8(y0, y1, y2, y3, y4, y5, y6, y7) = sliced;
+                sliced = Gf8(y0.inv_shift_row(),
+                             y1.inv_shift_row(),
+                             y2.inv_shift_row(),
+                             y3.inv_shift_row(),
+                             y4.inv_shift_row(),
+                             y5.inv_shift_row(),
+                             y6.inv_shift_row(),
+                             y7.inv_shift_row());
+            }
+            sliced
+        })
+        .collect()
+}
+
+fn fixslice_encrypt_core(state: Gf8<u16>, sk: &[Gf8<u16>]) -> Gf8<u16> {
+    let last = sk.len() - 1;
+
+    let mut tmp = state.add_round_key(&sk[0]);
+
+    for (i, subkey) in sk[1..last].iter().enumerate() {
+        let class = (i as u32 + 1) % 4;
+        tmp = tmp.sub_bytes();
+        tmp = mix_columns_class(tmp, class);
+        tmp = tmp.add_round_key(subkey);
+    }
+
+    tmp = tmp.sub_bytes();
+    tmp = tmp.add_round_key(&sk[last]);
+
+    // Undo the net ShiftRows offset the round keys couldn't cancel on their own: the state is
+    // left `shift_rows^(rounds % 4)` ahead of where it needs to be.
+    for _ in 0..(last as u32 % 4) {
+        tmp = tmp.shift_rows();
+    }
+
+    tmp
+}
+
+// Builds the bit-sliced, fixsliced decryption round-key schedule: the "equivalent inverse cipher"
+// decryption round keys from `create_round_keys`, reordered into the sequence `decrypt_core`
+// actually consumes them in (subkey `rounds` first, then `rounds - 1` down to `0`) and each
+// pre-rotated by `shift_row^dr`, where `dr` is its position in that consumption order - the
+// decryption-side mirror of `fixslice_round_keys` above.
+fn fixslice_decryption_round_keys(key: &[u8], rounds: usize) -> Vec<Gf8<u16>> {
+    let mut tmp = vec![[0u32; 4]; rounds + 1];
+    create_round_keys(key, KeyType::Decryption, &mut tmp);
+
+    (0..rounds + 1)
+        .map(|dr| {
+            let rk = &tmp[rounds - dr];
+            let Gf8(x0, x1, x2, x3, x4, x5, x6, x7) =
+                bit_slice_4x4_with_u16(rk[0], rk[1], rk[2], rk[3]);
+            let mut sliced = Gf8(x0, x1, x2, x3, x4, x5, x6, x7);
+            for _ in 0..(dr % 4) {
+                let Gf8(y0, y1, y2, y3, y4, y5, y6, y7) = sliced;
+                sliced = Gf8(y0.shift_row(),
+                             y1.shift_row(),
+                             y2.shift_row(),
+                             y3.shift_row(),
+                             y4.shift_row(),
+                             y5.shift_row(),
+                             y6.shift_row(),
+                             y7.shift_row());
+            }
+            sliced
+        })
+        .collect()
+}
+
+fn fixslice_decrypt_core(state: Gf8<u16>, dsk: &[Gf8<u16>]) -> Gf8<u16> {
+    let last = dsk.len() - 1;
+
+    let mut tmp = state.add_round_key(&dsk[0]);
+
+    for (i, subkey) in dsk[1..last].iter().enumerate() {
+        let class = (i as u32 + 1) % 4;
+        tmp = tmp.inv_sub_bytes();
+        tmp = inv_mix_columns_class(tmp, class);
+        tmp = tmp.add_round_key(subkey);
+    }
+
+    tmp = tmp.inv_sub_bytes();
+    tmp = tmp.add_round_key(&dsk[last]);
+
+    // Undo the net InvShiftRows offset the round keys couldn't cancel on their own.
+    for _ in 0..(last as u32 % 4) {
+        tmp = tmp.inv_shift_rows();
+    }
+
+    tmp
+}
+
+macro_rules! define_aes_fixslice_struct {
+    ($name:ident, $rounds:expr) => {
+        #[derive(Clone)]
+        pub struct $name {
+            sk: Vec<Gf8<u16>>,
+        }
+
+        impl $name {
+            pub fn new(key: &[u8]) -> $name {
+                $name { sk: fixslice_round_keys(key, $rounds) }
+            }
+        }
+
+        impl BlockEncrypt for $name {
+            type BlockSize = U16;
+
+            fn encrypt_block<I, O>(&self, input: I, mut output: O)
+                where I: AsRef<[u8]>,
+                      O: AsMut<[u8]>
+                {
+                    let bs = bit_slice_1x16_with_u16(input.as_ref());
+                    let bs = fixslice_encrypt_core(bs, &self.sk);
+                    un_bit_slice_1x16_with_u16(&bs, output.as_mut());
+                }
+        }
+    }
+}
+
+macro_rules! define_aes_fixslice_dec_struct {
+    ($name:ident, $rounds:expr) => {
+        #[derive(Clone)]
+        pub struct $name {
+            sk: Vec<Gf8<u16>>,
+        }
+
+        impl $name {
+            pub fn new(key: &[u8]) -> $name {
+                $name { sk: fixslice_decryption_round_keys(key, $rounds) }
+            }
+        }
+
+        impl BlockDecrypt for $name {
+            type BlockSize = U16;
+
+            fn decrypt_block<I, O>(&self, input: I, mut output: O)
+                where I: AsRef<[u8]>,
+                      O: AsMut<[u8]>
+                {
+                    let bs = bit_slice_1x16_with_u16(input.as_ref());
+                    let bs = fixslice_decrypt_core(bs, &self.sk);
+                    un_bit_slice_1x16_with_u16(&bs, output.as_mut());
+                }
+        }
+    }
+}
+
+define_aes_fixslice_struct!(AesFixslice128Encryptor, 10);
+define_aes_fixslice_struct!(AesFixslice256Encryptor, 14);
+
+define_aes_fixslice_dec_struct!(AesFixslice128Decryptor, 10);
+define_aes_fixslice_dec_struct!(AesFixslice256Decryptor, 14);
+
+#[cfg(test)]
+mod tests {
+    use super::{AesFixslice128Decryptor, AesFixslice128Encryptor, AesFixslice256Decryptor,
+                AesFixslice256Encryptor};
+    use super::super::super::test_vectors::{KEY_128, KEY_256, PLAINTEXT, CIPHERTEXT_128,
+                                             CIPHERTEXT_256};
+    use block::{BlockDecrypt, BlockEncrypt};
+
+    #[test]
+    fn matches_fips_197_aes_128() {
+        let cipher = AesFixslice128Encryptor::new(&KEY_128);
+        let mut out = [0u8; 16];
+        cipher.encrypt_block(&PLAINTEXT[..], &mut out[..]);
+        assert_eq!(out, CIPHERTEXT_128);
+    }
+
+    #[test]
+    fn matches_fips_197_aes_256() {
+        let cipher = AesFixslice256Encryptor::new(&KEY_256);
+        let mut out = [0u8; 16];
+        cipher.encrypt_block(&PLAINTEXT[..], &mut out[..]);
+        assert_eq!(out, CIPHERTEXT_256);
+    }
+
+    #[test]
+    fn decrypts_fips_197_aes_128() {
+        let cipher = AesFixslice128Decryptor::new(&KEY_128);
+        let mut out = [0u8; 16];
+        cipher.decrypt_block(&CIPHERTEXT_128[..], &mut out[..]);
+        assert_eq!(out, PLAINTEXT);
+    }
+
+    #[test]
+    fn decrypts_fips_197_aes_256() {
+        let cipher = AesFixslice256Decryptor::new(&KEY_256);
+        let mut out = [0u8; 16];
+        cipher.decrypt_block(&CIPHERTEXT_256[..], &mut out[..]);
+        assert_eq!(out, PLAINTEXT);
+    }
+
+    #[test]
+    fn round_trips_arbitrary_blocks() {
+        let key_128: [u8; 16] = *b"0123456789abcdef";
+        let enc_128 = AesFixslice128Encryptor::new(&key_128);
+        let dec_128 = AesFixslice128Decryptor::new(&key_128);
+        let mut ciphertext = [0u8; 16];
+        enc_128.encrypt_block(&PLAINTEXT[..], &mut ciphertext[..]);
+        let mut recovered = [0u8; 16];
+        dec_128.decrypt_block(&ciphertext[..], &mut recovered[..]);
+        assert_eq!(recovered, PLAINTEXT);
+
+        let key_256: [u8; 32] = *b"0123456789abcdef0123456789abcdef";
+        let enc_256 = AesFixslice256Encryptor::new(&key_256);
+        let dec_256 = AesFixslice256Decryptor::new(&key_256);
+        let mut ciphertext = [0u8; 16];
+        enc_256.encrypt_block(&PLAINTEXT[..], &mut ciphertext[..]);
+        let mut recovered = [0u8; 16];
+        dec_256.decrypt_block(&ciphertext[..], &mut recovered[..]);
+        assert_eq!(recovered, PLAINTEXT);
+    }
+}
@@ -0,0 +1,221 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A fully bit-sliced AES key schedule, for callers who want every step of key setup - not just
+//! encryption/decryption - to stay on the one `Gf8::sub_bytes` S-box path the rest of this module
+//! uses, with no intermediate plain `u32` words to reason about.
+//!
+//! `create_round_keys` in `super` already runs its SubWord step through `sub_word`, which itself
+//! bit-slices a single word and calls `Gf8::sub_bytes`, so that step was never table-driven. What
+//! it does do is carry the word-to-word XOR chaining and RCON addition as plain `u32` arithmetic
+//! between bit-slicing calls, and only bit-slices each finished round key afterwards. This module
+//! instead keeps every word of the schedule - including the raw key words - as a single-column
+//! `Gf8<u16>` (the same one-word representation `bit_slice_4x1_with_u16` produces) from the moment
+//! it's read out of the key, chaining XORs, RCON addition, and the AES-256 extra SubWord entirely
+//! as `Gf8` operations, and only returns to plain `u32`s to gather each group of 4 finished words
+//! back into the 4-column round-key layout `encrypt_core`/`decrypt_core` expect - a data movement
+//! step, not a data-dependent lookup.
+//!
+//! The byte-rotation SubWord needs before its S-box step turns out to be exactly `ror1` (a
+//! nibble-granular `rotate_right(4)`) applied to every plane, since each nibble of a single-column
+//! `Gf8<u16>` holds one byte of the word and a nibble rotation is precisely a byte rotation here.
+//! Decryption's extra `InvMixColumns` pass over the inner round keys is also just
+//! `Gf8::inv_mix_columns` applied directly to each finished round key: AES's InvMixColumns mixes
+//! within a column and never across columns, so running it over all 4 columns of a round key at
+//! once gives the same result as running the scalar `inv_mcol` helper on each of its words.
+
+use super::{AesBitValueOps, AesOps, Gf8, KeyType, RCON, bit_slice_4x1_with_u16,
+            bit_slice_4x4_with_u16, un_bit_slice_4x1_with_u16};
+use block::{BlockDecrypt, BlockEncrypt};
+use typenum::consts::U16;
+
+fn rotate_word_right_8(g: Gf8<u16>) -> Gf8<u16> {
+    let Gf8(x0, x1, x2, x3, x4, x5, x6, x7) = g;
+    Gf8(x0.ror1(), x1.ror1(), x2.ror1(), x3.ror1(), x4.ror1(), x5.ror1(), x6.ror1(), x7.ror1())
+}
+
+// Runs the AES key schedule entirely in the bit-sliced domain and returns the finished per-round
+// `Gf8<u16>` round keys directly, ready for `encrypt_core`/`decrypt_core`.
+fn bitsliced_round_keys(key: &[u8], key_type: KeyType) -> Vec<Gf8<u16>> {
+    let (key_words, rounds) = match key.len() {
+        16 => (4, 10),
+        24 => (6, 12),
+        32 => (8, 14),
+        _ => panic!("Invalid AES key size."),
+    };
+
+    let total_words = (rounds + 1) * 4;
+    let mut words: Vec<Gf8<u16>> = Vec::with_capacity(total_words);
+
+    for chunk in key.chunks(4).take(key_words) {
+        let w = (chunk[0] as u32) | ((chunk[1] as u32) << 8) | ((chunk[2] as u32) << 16) |
+                ((chunk[3] as u32) << 24);
+        words.push(bit_slice_4x1_with_u16(w));
+    }
+
+    for i in key_words..total_words {
+        let mut tmp = words[i - 1];
+        if i % key_words == 0 {
+            tmp = rotate_word_right_8(tmp).sub_bytes() +
+                  bit_slice_4x1_with_u16(RCON[(i / key_words) - 1]);
+        } else if key_words == 8 && (i % key_words) == 4 {
+            tmp = tmp.sub_bytes();
+        }
+        words.push(words[i - key_words] + tmp);
+    }
+
+    let mut round_keys: Vec<Gf8<u16>> = (0..rounds + 1)
+        .map(|round| {
+            let a = un_bit_slice_4x1_with_u16(&words[round * 4]);
+            let b = un_bit_slice_4x1_with_u16(&words[round * 4 + 1]);
+            let c = un_bit_slice_4x1_with_u16(&words[round * 4 + 2]);
+            let d = un_bit_slice_4x1_with_u16(&words[round * 4 + 3]);
+            bit_slice_4x4_with_u16(a, b, c, d)
+        })
+        .collect();
+
+    if let KeyType::Decryption = key_type {
+        for rk in &mut round_keys[1..rounds] {
+            *rk = rk.inv_mix_columns();
+        }
+    }
+
+    round_keys
+}
+
+macro_rules! define_aes_bitsliced_struct {
+    ($name:ident, $rounds:expr) => {
+        #[derive(Clone)]
+        pub struct $name {
+            sk: Vec<Gf8<u16>>,
+        }
+    }
+}
+
+macro_rules! define_aes_bitsliced_enc {
+    ($name:ident, $rounds:expr) => {
+        impl $name {
+            pub fn new(key: &[u8]) -> $name {
+                $name { sk: bitsliced_round_keys(key, KeyType::Encryption) }
+            }
+        }
+
+        impl BlockEncrypt for $name {
+            type BlockSize = U16;
+
+            fn encrypt_block<I, O>(&self, input: I, mut output: O)
+                where I: AsRef<[u8]>,
+                      O: AsMut<[u8]>
+                {
+                    let bs = super::bit_slice_1x16_with_u16(input.as_ref());
+                    let bs = super::encrypt_core(&bs, &self.sk);
+                    super::un_bit_slice_1x16_with_u16(&bs, output.as_mut());
+                }
+        }
+    }
+}
+
+macro_rules! define_aes_bitsliced_dec {
+    ($name:ident, $rounds:expr) => {
+        impl $name {
+            pub fn new(key: &[u8]) -> $name {
+                $name { sk: bitsliced_round_keys(key, KeyType::Decryption) }
+            }
+        }
+
+        impl BlockDecrypt for $name {
+            type BlockSize = U16;
+
+            fn decrypt_block<I, O>(&self, input: I, mut output: O)
+                where I: AsRef<[u8]>,
+                      O: AsMut<[u8]>
+                {
+                    let bs = super::bit_slice_1x16_with_u16(input.as_ref());
+                    let bs = super::decrypt_core(&bs, &self.sk);
+                    super::un_bit_slice_1x16_with_u16(&bs, output.as_mut());
+                }
+        }
+    }
+}
+
+define_aes_bitsliced_struct!(AesConstantTime128Encryptor, 10);
+define_aes_bitsliced_struct!(AesConstantTime128Decryptor, 10);
+define_aes_bitsliced_enc!(AesConstantTime128Encryptor, 10);
+define_aes_bitsliced_dec!(AesConstantTime128Decryptor, 10);
+
+define_aes_bitsliced_struct!(AesConstantTime192Encryptor, 12);
+define_aes_bitsliced_struct!(AesConstantTime192Decryptor, 12);
+define_aes_bitsliced_enc!(AesConstantTime192Encryptor, 12);
+define_aes_bitsliced_dec!(AesConstantTime192Decryptor, 12);
+
+define_aes_bitsliced_struct!(AesConstantTime256Encryptor, 14);
+define_aes_bitsliced_struct!(AesConstantTime256Decryptor, 14);
+define_aes_bitsliced_enc!(AesConstantTime256Encryptor, 14);
+define_aes_bitsliced_dec!(AesConstantTime256Decryptor, 14);
+
+#[cfg(test)]
+mod tests {
+    use super::{AesConstantTime128Decryptor, AesConstantTime128Encryptor,
+                AesConstantTime192Decryptor, AesConstantTime192Encryptor,
+                AesConstantTime256Decryptor, AesConstantTime256Encryptor};
+    use super::super::super::test_vectors::{KEY_128, KEY_192, KEY_256, PLAINTEXT, CIPHERTEXT_128,
+                                             CIPHERTEXT_192, CIPHERTEXT_256};
+    use block::{BlockDecrypt, BlockEncrypt};
+
+    #[test]
+    fn matches_fips_197_aes_128() {
+        let enc = AesConstantTime128Encryptor::new(&KEY_128);
+        let mut ciphertext = [0u8; 16];
+        enc.encrypt_block(&PLAINTEXT[..], &mut ciphertext[..]);
+        assert_eq!(ciphertext, CIPHERTEXT_128);
+
+        let dec = AesConstantTime128Decryptor::new(&KEY_128);
+        let mut plaintext = [0u8; 16];
+        dec.decrypt_block(&ciphertext[..], &mut plaintext[..]);
+        assert_eq!(plaintext, PLAINTEXT);
+    }
+
+    #[test]
+    fn matches_fips_197_aes_192() {
+        let enc = AesConstantTime192Encryptor::new(&KEY_192);
+        let mut ciphertext = [0u8; 16];
+        enc.encrypt_block(&PLAINTEXT[..], &mut ciphertext[..]);
+        assert_eq!(ciphertext, CIPHERTEXT_192);
+
+        let dec = AesConstantTime192Decryptor::new(&KEY_192);
+        let mut plaintext = [0u8; 16];
+        dec.decrypt_block(&ciphertext[..], &mut plaintext[..]);
+        assert_eq!(plaintext, PLAINTEXT);
+    }
+
+    #[test]
+    fn matches_fips_197_aes_256() {
+        let enc = AesConstantTime256Encryptor::new(&KEY_256);
+        let mut ciphertext = [0u8; 16];
+        enc.encrypt_block(&PLAINTEXT[..], &mut ciphertext[..]);
+        assert_eq!(ciphertext, CIPHERTEXT_256);
+
+        let dec = AesConstantTime256Decryptor::new(&KEY_256);
+        let mut plaintext = [0u8; 16];
+        dec.decrypt_block(&ciphertext[..], &mut plaintext[..]);
+        assert_eq!(plaintext, PLAINTEXT);
+    }
+
+    #[test]
+    fn round_trips_arbitrary_blocks() {
+        let key: [u8; 24] = *b"0123456789abcdef01234567";
+        let enc = AesConstantTime192Encryptor::new(&key);
+        let dec = AesConstantTime192Decryptor::new(&key);
+        let plaintext: [u8; 16] = *b"abcdefghijklmnop";
+
+        let mut ciphertext = [0u8; 16];
+        enc.encrypt_block(&plaintext[..], &mut ciphertext[..]);
+        let mut recovered = [0u8; 16];
+        dec.decrypt_block(&ciphertext[..], &mut recovered[..]);
+
+        assert_eq!(recovered, plaintext);
+    }
+}
@@ -73,7 +73,9 @@
 //! Sliced the input data. After the completion of the AES operation, we then un-Bit Slice the data
 //! to give us our output. Clearly, the more bits that we can process at once, the faster this will go -
 //! thus, the version that processes 8 blocks at once is roughly 8 times faster than processing just a
-//! single block at a time.
+//! single block at a time. A third implementation packs 4 blocks into a plain `u64` (64 bits per
+//! slice is exactly 4 blocks' worth of one-bit-per-byte), giving a portable middle ground on 64-bit
+//! targets that don't have a fast `u32x4`.
 //!
 //! The ShiftRows step is fairly straight-forward to implement on the Bit Sliced state. The MixColumns
 //! and especially the SubBytes steps are more complicated. This implementation draws heavily on the
@@ -117,13 +119,25 @@ use std::ops::{BitAnd, BitXor, Not};
 use byteorder::{LittleEndian, ByteOrder};
 use typenum::consts::U16;
 
-use block::{BlockEncrypt, BlockDecrypt};
+use block::{BlockEncrypt, BlockDecrypt, BlockEncryptorX4, BlockDecryptorX4, BlockEncryptorX8,
+            BlockDecryptorX8};
 
 use self::simd::*;
 use self::gf::*;
 
+pub use self::fixslice::{AesFixslice128Decryptor, AesFixslice128Encryptor, AesFixslice256Decryptor,
+                          AesFixslice256Encryptor};
+pub use self::keyschedule::{AesConstantTime128Decryptor, AesConstantTime128Encryptor,
+                             AesConstantTime192Decryptor, AesConstantTime192Encryptor,
+                             AesConstantTime256Decryptor, AesConstantTime256Encryptor};
+pub use self::modes::{apply, cbc_decrypt, cbc_encrypt, cfb_decrypt, cfb_encrypt, ctr_xor, Mode,
+                       ofb_xor};
+
 mod simd;
 mod gf;
+mod fixslice;
+mod keyschedule;
+pub mod modes;
 
 macro_rules! define_aes_struct {
     ($name:ident, $rounds:expr) => {
@@ -264,26 +278,165 @@ macro_rules! define_aes_dec_x8 {
     }
 }
 
-// define_aes_struct_x8!(AesSafe128EncryptorX8, 10);
-// define_aes_struct_x8!(AesSafe128DecryptorX8, 10);
-// define_aes_impl_x8!(AesSafe128EncryptorX8, Encryption, 10, 16);
-// define_aes_impl_x8!(AesSafe128DecryptorX8, Decryption, 10, 16);
-// define_aes_enc_x8!(AesSafe128EncryptorX8, 10);
-// define_aes_dec_x8!(AesSafe128DecryptorX8, 10);
+define_aes_struct_x8!(AesSafe128EncryptorX8, 10);
+define_aes_struct_x8!(AesSafe128DecryptorX8, 10);
+define_aes_impl_x8!(AesSafe128EncryptorX8, Encryption, 10, 16);
+define_aes_impl_x8!(AesSafe128DecryptorX8, Decryption, 10, 16);
+define_aes_enc_x8!(AesSafe128EncryptorX8, 10);
+define_aes_dec_x8!(AesSafe128DecryptorX8, 10);
+
+define_aes_struct_x8!(AesSafe192EncryptorX8, 12);
+define_aes_struct_x8!(AesSafe192DecryptorX8, 12);
+define_aes_impl_x8!(AesSafe192EncryptorX8, Encryption, 12, 24);
+define_aes_impl_x8!(AesSafe192DecryptorX8, Decryption, 12, 24);
+define_aes_enc_x8!(AesSafe192EncryptorX8, 12);
+define_aes_dec_x8!(AesSafe192DecryptorX8, 12);
+
+define_aes_struct_x8!(AesSafe256EncryptorX8, 14);
+define_aes_struct_x8!(AesSafe256DecryptorX8, 14);
+define_aes_impl_x8!(AesSafe256EncryptorX8, Encryption, 14, 32);
+define_aes_impl_x8!(AesSafe256DecryptorX8, Decryption, 14, 32);
+define_aes_enc_x8!(AesSafe256EncryptorX8, 14);
+define_aes_dec_x8!(AesSafe256DecryptorX8, 14);
+
+#[cfg(test)]
+mod x8_tests {
+    use super::{AesSafe128DecryptorX8, AesSafe128EncryptorX8, AesSafe256DecryptorX8,
+                AesSafe256EncryptorX8};
+    use super::super::test_vectors::{KEY_128, KEY_256, PLAINTEXT, CIPHERTEXT_128, CIPHERTEXT_256};
+    use block::{BlockDecryptorX8, BlockEncryptorX8};
+
+    fn repeat8(block: &[u8; 16]) -> [u8; 128] {
+        let mut out = [0u8; 128];
+        for chunk in out.chunks_mut(16) {
+            chunk.copy_from_slice(block);
+        }
+        out
+    }
+
+    #[test]
+    fn matches_fips_197_aes_128_across_all_eight_lanes() {
+        let cipher = AesSafe128EncryptorX8::new(&KEY_128);
+        let input = repeat8(&PLAINTEXT);
+        let mut output = [0u8; 128];
+        cipher.encrypt_block_x8(&input, &mut output);
+        assert_eq!(&output[..], &repeat8(&CIPHERTEXT_128)[..]);
+    }
+
+    #[test]
+    fn matches_fips_197_aes_256_across_all_eight_lanes() {
+        let cipher = AesSafe256EncryptorX8::new(&KEY_256);
+        let input = repeat8(&PLAINTEXT);
+        let mut output = [0u8; 128];
+        cipher.encrypt_block_x8(&input, &mut output);
+        assert_eq!(&output[..], &repeat8(&CIPHERTEXT_256)[..]);
+    }
+
+    #[test]
+    fn round_trips_eight_distinct_blocks() {
+        let enc = AesSafe128EncryptorX8::new(&KEY_128);
+        let dec = AesSafe128DecryptorX8::new(&KEY_128);
+
+        let mut input = [0u8; 128];
+        for (i, b) in input.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+
+        let mut ciphertext = [0u8; 128];
+        enc.encrypt_block_x8(&input, &mut ciphertext);
+        let mut recovered = [0u8; 128];
+        dec.decrypt_block_x8(&ciphertext, &mut recovered);
+
+        assert_eq!(&recovered[..], &input[..]);
+    }
+}
 
-// define_aes_struct_x8!(AesSafe192EncryptorX8, 12);
-// define_aes_struct_x8!(AesSafe192DecryptorX8, 12);
-// define_aes_impl_x8!(AesSafe192EncryptorX8, Encryption, 12, 24);
-// define_aes_impl_x8!(AesSafe192DecryptorX8, Decryption, 12, 24);
-// define_aes_enc_x8!(AesSafe192EncryptorX8, 12);
-// define_aes_dec_x8!(AesSafe192DecryptorX8, 12);
+macro_rules! define_aes_struct_x4 {
+    ($name:ident, $rounds:expr) => {
+        #[derive(Clone, Copy)]
+        pub struct $name {
+            sk: [Gf8<u64>; ($rounds + 1)]
+        }
+    }
+}
+
+macro_rules! define_aes_impl_x4 {
+    ($name:ident, $mode:ident, $rounds:expr, $key_size:expr) => {
+        impl $name {
+            pub fn new(key: &[u8]) -> $name {
+                let mut a =  $name {
+                    sk: [Gf8::default(); ($rounds + 1)]
+                };
+                let mut tmp = [[0u32; 4]; ($rounds + 1)];
+                create_round_keys(key, KeyType::$mode, &mut tmp);
+                for i in 0..$rounds + 1 {
+                    a.sk[i] = bit_slice_fill_4x4_with_u64(
+                        tmp[i][0],
+                        tmp[i][1],
+                        tmp[i][2],
+                        tmp[i][3]);
+                }
+                a
+            }
+        }
+    }
+}
+
+macro_rules! define_aes_enc_x4 {
+    ($name:ident, $rounds:expr) => {
+        impl BlockEncryptorX4 for $name {
+            fn block_size(&self) -> usize { 16 }
+            fn encrypt_block_x4(&self, input: &[u8], output: &mut [u8]) {
+                let bs = bit_slice_1x64_with_u64(input);
+                let bs2 = encrypt_core(&bs, &self.sk);
+                un_bit_slice_1x64_with_u64(&bs2, output);
+            }
+        }
+    }
+}
+
+macro_rules! define_aes_dec_x4 {
+    ( $name:ident, $rounds:expr) => {
+        impl BlockDecryptorX4 for $name {
+            fn block_size(&self) -> usize { 16 }
+            fn decrypt_block_x4(&self, input: &[u8], output: &mut [u8]) {
+                let bs = bit_slice_1x64_with_u64(input);
+                let bs2 = decrypt_core(&bs, &self.sk);
+                un_bit_slice_1x64_with_u64(&bs2, output);
+            }
+        }
+    }
+}
 
-// define_aes_struct_x8!(AesSafe256EncryptorX8, 14);
-// define_aes_struct_x8!(AesSafe256DecryptorX8, 14);
-// define_aes_impl_x8!(AesSafe256EncryptorX8, Encryption, 14, 32);
-// define_aes_impl_x8!(AesSafe256DecryptorX8, Decryption, 14, 32);
-// define_aes_enc_x8!(AesSafe256EncryptorX8, 14);
-// define_aes_dec_x8!(AesSafe256DecryptorX8, 14);
+define_aes_struct_x4!(AesSafe128EncryptorX4, 10);
+define_aes_struct_x4!(AesSafe128DecryptorX4, 10);
+define_aes_impl_x4!(AesSafe128EncryptorX4, Encryption, 10, 16);
+define_aes_impl_x4!(AesSafe128DecryptorX4, Decryption, 10, 16);
+define_aes_enc_x4!(AesSafe128EncryptorX4, 10);
+define_aes_dec_x4!(AesSafe128DecryptorX4, 10);
+
+define_aes_struct_x4!(AesSafe192EncryptorX4, 12);
+define_aes_struct_x4!(AesSafe192DecryptorX4, 12);
+define_aes_impl_x4!(AesSafe192EncryptorX4, Encryption, 12, 24);
+define_aes_impl_x4!(AesSafe192DecryptorX4, Decryption, 12, 24);
+define_aes_enc_x4!(AesSafe192EncryptorX4, 12);
+define_aes_dec_x4!(AesSafe192DecryptorX4, 12);
+
+define_aes_struct_x4!(AesSafe256EncryptorX4, 14);
+define_aes_struct_x4!(AesSafe256DecryptorX4, 14);
+define_aes_impl_x4!(AesSafe256EncryptorX4, Encryption, 14, 32);
+define_aes_impl_x4!(AesSafe256DecryptorX4, Decryption, 14, 32);
+define_aes_enc_x4!(AesSafe256EncryptorX4, 14);
+define_aes_dec_x4!(AesSafe256DecryptorX4, 14);
+
+/// Exposes the plain (non-bit-sliced) AES-192 encryption round keys so `aesni` can load them
+/// directly into hardware registers instead of running its own key-assist schedule for this one
+/// key size - see `aesni::Aes192`.
+pub fn encryption_round_keys_192(key: &[u8]) -> [[u32; 4]; 13] {
+    let mut round_keys = [[0u32; 4]; 13];
+    create_round_keys(key, KeyType::Encryption, &mut round_keys);
+    round_keys
+}
 
 fn ffmulx(x: u32) -> u32 {
     let m1: u32 = 0x80808080;
@@ -629,6 +782,53 @@ fn un_bit_slice_1x128_with_u32x4(bs: Gf8<u32x4>, output: &mut [u8]) {
     x7.write_row_major(&mut output[112..128])
 }
 
+// Bit Slice four 16 byte blocks (64 bytes total) into a Gf8<u64>: each of the eight 64-bit planes
+// packs one 16-bit lane per block, built by reusing `bit_slice_1x16_with_u16`'s single-block,
+// column-major layout and placing each block's resulting plane bits at `block * 16` in the lane.
+fn bit_slice_1x64_with_u64(data: &[u8]) -> Gf8<u64> {
+    let mut planes = [0u64; 8];
+    for block in 0..4 {
+        let Gf8(x0, x1, x2, x3, x4, x5, x6, x7) =
+            bit_slice_1x16_with_u16(&data[block * 16..block * 16 + 16]);
+        let lane = [x0, x1, x2, x3, x4, x5, x6, x7];
+        for (plane, &bits) in planes.iter_mut().zip(lane.iter()) {
+            *plane |= (bits as u64) << (block * 16);
+        }
+    }
+    Gf8(planes[0], planes[1], planes[2], planes[3], planes[4], planes[5], planes[6], planes[7])
+}
+
+// Bit slice a set of 4 u32s by filling all four packed block lanes with those repeated values.
+// This is used as part of bit slicing the round keys for the four-block-parallel path.
+fn bit_slice_fill_4x4_with_u64(a: u32, b: u32, c: u32, d: u32) -> Gf8<u64> {
+    let mut tmp = [0u8; 64];
+    for i in 0..4 {
+        LittleEndian::write_u32(&mut tmp[i * 16..i * 16 + 4], a);
+        LittleEndian::write_u32(&mut tmp[i * 16 + 4..i * 16 + 8], b);
+        LittleEndian::write_u32(&mut tmp[i * 16 + 8..i * 16 + 12], c);
+        LittleEndian::write_u32(&mut tmp[i * 16 + 12..i * 16 + 16], d);
+    }
+    bit_slice_1x64_with_u64(&tmp)
+}
+
+// Un bit slice a Gf8<u64> into a 64 byte buffer, the inverse of `bit_slice_1x64_with_u64`.
+fn un_bit_slice_1x64_with_u64(bs: &Gf8<u64>, output: &mut [u8]) {
+    let Gf8(x0, x1, x2, x3, x4, x5, x6, x7) = *bs;
+
+    for block in 0..4 {
+        let shift = block * 16;
+        let lane = Gf8(((x0 >> shift) & 0xffff) as u16,
+                       ((x1 >> shift) & 0xffff) as u16,
+                       ((x2 >> shift) & 0xffff) as u16,
+                       ((x3 >> shift) & 0xffff) as u16,
+                       ((x4 >> shift) & 0xffff) as u16,
+                       ((x5 >> shift) & 0xffff) as u16,
+                       ((x6 >> shift) & 0xffff) as u16,
+                       ((x7 >> shift) & 0xffff) as u16);
+        un_bit_slice_1x16_with_u16(&lane, &mut output[block * 16..block * 16 + 16]);
+    }
+}
+
 // // The Gf2Ops, Gf4Ops, and Gf8Ops traits specify the functions needed to calculate the AES S-Box
 // // values. This particuar implementation of those S-Box values is taken from [7], so that is where
 // // to look for details on how all that all works. This includes the transformations matrices defined
@@ -780,4 +980,107 @@ impl AesBitValueOps for u32x4 {
     fn ror3(self) -> u32x4 {
         u32x4(self.3, self.0, self.1, self.2)
     }
+}
+
+// Repeats a 16-bit mask into each of the four packed 16-bit block lanes of a u64.
+fn lane_mask_u64(m: u16) -> u64 {
+    let m = m as u64;
+    m | (m << 16) | (m << 32) | (m << 48)
+}
+
+// Rotates each of the four packed 16-bit block lanes of a u64 right by `n` bits independently,
+// by extracting, rotating, and reinserting each lane in turn - used for `ror1`/`ror2`/`ror3` below,
+// where a plain 64-bit `rotate_right` would incorrectly mix bits across lane boundaries.
+fn rotate_right_u16_lanes(x: u64, n: u32) -> u64 {
+    let mut result = 0u64;
+    for lane in 0..4 {
+        let shift = lane * 16;
+        let v = ((x >> shift) & 0xffff) as u16;
+        result |= (v.rotate_right(n) as u64) << shift;
+    }
+    result
+}
+
+impl AesBitValueOps for u64 {
+    // Same row permutation as the single-block `u16` case (see its `shift_row`), applied
+    // independently within each of the four packed 16-bit block lanes. Every mask/shift pair here
+    // moves bits by at most 3 positions within a 4-bit nibble, so repeating the `u16` masks across
+    // all four lanes can never leak bits across a lane boundary.
+    fn shift_row(self) -> Self {
+        (self & lane_mask_u64(0x000f)) | ((self & lane_mask_u64(0x00e0)) >> 1) |
+        ((self & lane_mask_u64(0x0010)) << 3) | ((self & lane_mask_u64(0x0c00)) >> 2) |
+        ((self & lane_mask_u64(0x0300)) << 2) | ((self & lane_mask_u64(0x8000)) >> 3) |
+        ((self & lane_mask_u64(0x7000)) << 1)
+    }
+
+    fn inv_shift_row(self) -> Self {
+        (self & lane_mask_u64(0x000f)) | ((self & lane_mask_u64(0x0080)) >> 3) |
+        ((self & lane_mask_u64(0x0070)) << 1) | ((self & lane_mask_u64(0x0c00)) >> 2) |
+        ((self & lane_mask_u64(0x0300)) << 2) | ((self & lane_mask_u64(0xe000)) >> 1) |
+        ((self & lane_mask_u64(0x1000)) << 3)
+    }
+
+    fn ror1(self) -> Self {
+        rotate_right_u16_lanes(self, 4)
+    }
+
+    fn ror2(self) -> Self {
+        rotate_right_u16_lanes(self, 8)
+    }
+
+    fn ror3(self) -> Self {
+        rotate_right_u16_lanes(self, 12)
+    }
+}
+
+#[cfg(test)]
+mod x4_tests {
+    use super::{AesSafe128DecryptorX4, AesSafe128EncryptorX4, AesSafe256DecryptorX4,
+                AesSafe256EncryptorX4};
+    use super::super::test_vectors::{KEY_128, KEY_256, PLAINTEXT, CIPHERTEXT_128, CIPHERTEXT_256};
+    use block::{BlockDecryptorX4, BlockEncryptorX4};
+
+    fn repeat4(block: &[u8; 16]) -> [u8; 64] {
+        let mut out = [0u8; 64];
+        for chunk in out.chunks_mut(16) {
+            chunk.copy_from_slice(block);
+        }
+        out
+    }
+
+    #[test]
+    fn matches_fips_197_aes_128_across_all_four_lanes() {
+        let cipher = AesSafe128EncryptorX4::new(&KEY_128);
+        let input = repeat4(&PLAINTEXT);
+        let mut output = [0u8; 64];
+        cipher.encrypt_block_x4(&input, &mut output);
+        assert_eq!(&output[..], &repeat4(&CIPHERTEXT_128)[..]);
+    }
+
+    #[test]
+    fn matches_fips_197_aes_256_across_all_four_lanes() {
+        let cipher = AesSafe256EncryptorX4::new(&KEY_256);
+        let input = repeat4(&PLAINTEXT);
+        let mut output = [0u8; 64];
+        cipher.encrypt_block_x4(&input, &mut output);
+        assert_eq!(&output[..], &repeat4(&CIPHERTEXT_256)[..]);
+    }
+
+    #[test]
+    fn round_trips_four_distinct_blocks() {
+        let enc = AesSafe128EncryptorX4::new(&KEY_128);
+        let dec = AesSafe128DecryptorX4::new(&KEY_128);
+
+        let mut input = [0u8; 64];
+        for (i, b) in input.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+
+        let mut ciphertext = [0u8; 64];
+        enc.encrypt_block_x4(&input, &mut ciphertext);
+        let mut recovered = [0u8; 64];
+        dec.decrypt_block_x4(&ciphertext, &mut recovered);
+
+        assert_eq!(&recovered[..], &input[..]);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,386 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! CTR and CBC-decrypt dispatch over the 8-block-parallel `*X8` primitives in `super`: both modes
+//! are trivially parallelizable (the keystream/plaintext blocks don't depend on each other), so
+//! eight blocks are handled per `encrypt_block_x8`/`decrypt_block_x8` call, with any remaining
+//! partial batch of fewer than eight blocks falling back to the single-block path. Callers build
+//! both a single-block cipher and its `X8` counterpart from the same key and pass both in.
+//!
+//! CBC-encrypt, CFB, and OFB are inherently serial - each block depends on the previous one's
+//! output - so `cbc_encrypt`/`cfb_encrypt`/`cfb_decrypt`/`ofb_xor` below only ever drive the
+//! single-block path. `Mode` and `apply` tie all six modes together behind one entry point that
+//! takes a mode (with its IV/nonce) and picks the right primitive automatically.
+
+use typenum::consts::U16;
+
+use block::{BlockDecrypt, BlockDecryptorX8, BlockEncrypt, BlockEncryptorX8};
+
+const BLOCK_SIZE: usize = 16;
+const WIDE_BLOCK: usize = 8 * BLOCK_SIZE;
+
+fn increment_counter(counter: &mut [u8; BLOCK_SIZE]) {
+    for byte in counter.iter_mut().rev() {
+        *byte = byte.wrapping_add(1);
+        if *byte != 0 {
+            break;
+        }
+    }
+}
+
+/// XORs a CTR keystream, seeded with the big-endian 128-bit `nonce`, into `data` in place.
+pub fn ctr_xor<S, X>(single: &S, x8: &X, nonce: &[u8; BLOCK_SIZE], data: &mut [u8])
+    where S: BlockEncrypt<BlockSize = U16>,
+          X: BlockEncryptorX8
+{
+    let mut counter = *nonce;
+
+    let mut chunks = data.chunks_mut(WIDE_BLOCK);
+    for chunk in chunks.by_ref() {
+        if chunk.len() < WIDE_BLOCK {
+            for block in chunk.chunks_mut(BLOCK_SIZE) {
+                let mut keystream = [0u8; BLOCK_SIZE];
+                single.encrypt_block(&counter[..], &mut keystream[..]);
+                for (b, k) in block.iter_mut().zip(keystream.iter()) {
+                    *b ^= *k;
+                }
+                increment_counter(&mut counter);
+            }
+            break;
+        }
+
+        let mut counters = [0u8; WIDE_BLOCK];
+        for block in counters.chunks_mut(BLOCK_SIZE) {
+            block.copy_from_slice(&counter);
+            increment_counter(&mut counter);
+        }
+
+        let mut keystream = [0u8; WIDE_BLOCK];
+        x8.encrypt_block_x8(&counters, &mut keystream);
+
+        for (b, k) in chunk.iter_mut().zip(keystream.iter()) {
+            *b ^= *k;
+        }
+    }
+}
+
+/// Decrypts `data` in place as CBC ciphertext, chaining against `iv`.
+pub fn cbc_decrypt<S, X>(single: &S, x8: &X, iv: &[u8; BLOCK_SIZE], data: &mut [u8])
+    where S: BlockDecrypt<BlockSize = U16>,
+          X: BlockDecryptorX8
+{
+    assert_eq!(data.len() % BLOCK_SIZE, 0);
+
+    let mut prev = *iv;
+    let mut offset = 0;
+
+    while data.len() - offset >= WIDE_BLOCK {
+        let ciphertext: [u8; WIDE_BLOCK] = {
+            let mut buf = [0u8; WIDE_BLOCK];
+            buf.copy_from_slice(&data[offset..offset + WIDE_BLOCK]);
+            buf
+        };
+
+        let mut plaintext = [0u8; WIDE_BLOCK];
+        x8.decrypt_block_x8(&ciphertext, &mut plaintext);
+
+        for (i, block) in plaintext.chunks_mut(BLOCK_SIZE).enumerate() {
+            if i == 0 {
+                for (b, c) in block.iter_mut().zip(prev.iter()) {
+                    *b ^= *c;
+                }
+            } else {
+                let chain = &ciphertext[(i - 1) * BLOCK_SIZE..i * BLOCK_SIZE];
+                for (b, c) in block.iter_mut().zip(chain.iter()) {
+                    *b ^= *c;
+                }
+            }
+        }
+
+        data[offset..offset + WIDE_BLOCK].copy_from_slice(&plaintext);
+        prev.copy_from_slice(&ciphertext[WIDE_BLOCK - BLOCK_SIZE..]);
+        offset += WIDE_BLOCK;
+    }
+
+    while offset < data.len() {
+        let mut ciphertext = [0u8; BLOCK_SIZE];
+        ciphertext.copy_from_slice(&data[offset..offset + BLOCK_SIZE]);
+
+        let mut plaintext = [0u8; BLOCK_SIZE];
+        single.decrypt_block(&ciphertext[..], &mut plaintext[..]);
+        for (b, c) in plaintext.iter_mut().zip(prev.iter()) {
+            *b ^= *c;
+        }
+
+        data[offset..offset + BLOCK_SIZE].copy_from_slice(&plaintext);
+        prev = ciphertext;
+        offset += BLOCK_SIZE;
+    }
+}
+
+/// Encrypts `data` in place as CBC ciphertext, chaining against `iv`. Each block's plaintext is
+/// XORed with the previous block's ciphertext before encryption, so unlike `cbc_decrypt` there's
+/// no batch of independent blocks to hand to the eight-wide path - every block depends on the one
+/// before it.
+pub fn cbc_encrypt<S>(single: &S, iv: &[u8; BLOCK_SIZE], data: &mut [u8])
+    where S: BlockEncrypt<BlockSize = U16>
+{
+    assert_eq!(data.len() % BLOCK_SIZE, 0);
+
+    let mut prev = *iv;
+    for block in data.chunks_mut(BLOCK_SIZE) {
+        for (b, p) in block.iter_mut().zip(prev.iter()) {
+            *b ^= *p;
+        }
+        let mut out = [0u8; BLOCK_SIZE];
+        single.encrypt_block(&block[..], &mut out[..]);
+        block.copy_from_slice(&out);
+        prev = out;
+    }
+}
+
+/// Encrypts `data` in place as CFB ciphertext, chaining against `iv`. The final block may be
+/// shorter than `BLOCK_SIZE`, in which case only the matching prefix of the keystream is used.
+pub fn cfb_encrypt<S>(single: &S, iv: &[u8; BLOCK_SIZE], data: &mut [u8])
+    where S: BlockEncrypt<BlockSize = U16>
+{
+    let mut prev = *iv;
+    for block in data.chunks_mut(BLOCK_SIZE) {
+        let mut keystream = [0u8; BLOCK_SIZE];
+        single.encrypt_block(&prev[..], &mut keystream[..]);
+        for (b, k) in block.iter_mut().zip(keystream.iter()) {
+            *b ^= *k;
+        }
+        prev[..block.len()].copy_from_slice(block);
+    }
+}
+
+/// Decrypts `data` in place as CFB ciphertext, chaining against `iv` - the inverse of
+/// `cfb_encrypt`.
+pub fn cfb_decrypt<S>(single: &S, iv: &[u8; BLOCK_SIZE], data: &mut [u8])
+    where S: BlockEncrypt<BlockSize = U16>
+{
+    let mut prev = *iv;
+    for block in data.chunks_mut(BLOCK_SIZE) {
+        let mut keystream = [0u8; BLOCK_SIZE];
+        single.encrypt_block(&prev[..], &mut keystream[..]);
+        prev[..block.len()].copy_from_slice(block);
+        for (b, k) in block.iter_mut().zip(keystream.iter()) {
+            *b ^= *k;
+        }
+    }
+}
+
+/// XORs an OFB keystream, seeded from `iv`, into `data` in place. Like CTR, OFB's keystream
+/// doesn't depend on the plaintext/ciphertext, so the same function serves both directions.
+pub fn ofb_xor<S>(single: &S, iv: &[u8; BLOCK_SIZE], data: &mut [u8])
+    where S: BlockEncrypt<BlockSize = U16>
+{
+    let mut feedback = *iv;
+    for block in data.chunks_mut(BLOCK_SIZE) {
+        let mut keystream = [0u8; BLOCK_SIZE];
+        single.encrypt_block(&feedback[..], &mut keystream[..]);
+        feedback = keystream;
+        for (b, k) in block.iter_mut().zip(keystream.iter()) {
+            *b ^= *k;
+        }
+    }
+}
+
+/// Identifies which streaming mode to run over `data`, bundling the IV/nonce it needs. `Ctr` and
+/// `CbcDecrypt` are trivially parallelizable and get routed to the eight-wide `x8` primitive by
+/// `apply`; `CbcEncrypt`, `CfbEncrypt`/`CfbDecrypt`, and `Ofb` are inherently serial and always
+/// run on the single-block `single` primitive.
+pub enum Mode<'a> {
+    Ctr(&'a [u8; BLOCK_SIZE]),
+    CbcEncrypt(&'a [u8; BLOCK_SIZE]),
+    CbcDecrypt(&'a [u8; BLOCK_SIZE]),
+    CfbEncrypt(&'a [u8; BLOCK_SIZE]),
+    CfbDecrypt(&'a [u8; BLOCK_SIZE]),
+    Ofb(&'a [u8; BLOCK_SIZE]),
+}
+
+/// Runs `mode` over `data` in place, using `x8` for the modes that can batch eight blocks at once
+/// and falling back to `single` for the inherently serial ones.
+pub fn apply<S, X>(mode: Mode, single: &S, x8: &X, data: &mut [u8])
+    where S: BlockEncrypt<BlockSize = U16> + BlockDecrypt<BlockSize = U16>,
+          X: BlockEncryptorX8 + BlockDecryptorX8
+{
+    match mode {
+        Mode::Ctr(nonce) => ctr_xor(single, x8, nonce, data),
+        Mode::CbcDecrypt(iv) => cbc_decrypt(single, x8, iv, data),
+        Mode::CbcEncrypt(iv) => cbc_encrypt(single, iv, data),
+        Mode::CfbEncrypt(iv) => cfb_encrypt(single, iv, data),
+        Mode::CfbDecrypt(iv) => cfb_decrypt(single, iv, data),
+        Mode::Ofb(iv) => ofb_xor(single, iv, data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, cbc_decrypt, cbc_encrypt, cfb_decrypt, cfb_encrypt, ctr_xor, ofb_xor, Mode};
+    use super::super::{AesSafe128Decryptor, AesSafe128DecryptorX8, AesSafe128Encryptor,
+                        AesSafe128EncryptorX8};
+    use block::{BlockDecrypt, BlockDecryptorX8, BlockEncrypt, BlockEncryptorX8};
+
+    const KEY: [u8; 16] = [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b,
+                           0x0c, 0x0d, 0x0e, 0x0f];
+
+    // `apply` needs a single type implementing both directions of a cipher - bundle the separate
+    // encryptor/decryptor structs the way `aesni::Aes128` does, for the single-block and X8 sides.
+    struct Bidirectional {
+        enc: AesSafe128Encryptor,
+        dec: AesSafe128Decryptor,
+    }
+
+    impl BlockEncrypt for Bidirectional {
+        type BlockSize = <AesSafe128Encryptor as BlockEncrypt>::BlockSize;
+
+        fn encrypt_block<I: AsRef<[u8]>, O: AsMut<[u8]>>(&self, input: I, output: O) {
+            self.enc.encrypt_block(input, output)
+        }
+    }
+
+    impl BlockDecrypt for Bidirectional {
+        type BlockSize = <AesSafe128Decryptor as BlockDecrypt>::BlockSize;
+
+        fn decrypt_block<I: AsRef<[u8]>, O: AsMut<[u8]>>(&self, input: I, output: O) {
+            self.dec.decrypt_block(input, output)
+        }
+    }
+
+    struct BidirectionalX8 {
+        enc: AesSafe128EncryptorX8,
+        dec: AesSafe128DecryptorX8,
+    }
+
+    impl BlockEncryptorX8 for BidirectionalX8 {
+        fn block_size(&self) -> usize {
+            self.enc.block_size()
+        }
+
+        fn encrypt_block_x8(&self, input: &[u8], output: &mut [u8]) {
+            self.enc.encrypt_block_x8(input, output)
+        }
+    }
+
+    impl BlockDecryptorX8 for BidirectionalX8 {
+        fn block_size(&self) -> usize {
+            self.dec.block_size()
+        }
+
+        fn decrypt_block_x8(&self, input: &[u8], output: &mut [u8]) {
+            self.dec.decrypt_block_x8(input, output)
+        }
+    }
+
+    // A length that forces both the eight-wide batch path and the single-block fallback to run.
+    fn sample_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn ctr_xor_round_trips_across_a_partial_final_batch() {
+        let single = AesSafe128Encryptor::new(&KEY);
+        let x8 = AesSafe128EncryptorX8::new(&KEY);
+        let nonce = [0u8; 16];
+
+        let plaintext = sample_data(8 * 16 + 3 * 16 + 5);
+        let mut buf = plaintext.clone();
+
+        ctr_xor(&single, &x8, &nonce, &mut buf);
+        assert_ne!(buf, plaintext);
+
+        ctr_xor(&single, &x8, &nonce, &mut buf);
+        assert_eq!(buf, plaintext);
+    }
+
+    #[test]
+    fn cbc_decrypt_inverts_cbc_encrypt_across_a_partial_final_batch() {
+        let single_enc = AesSafe128Encryptor::new(&KEY);
+        let single_dec = AesSafe128Decryptor::new(&KEY);
+        let x8_dec = AesSafe128DecryptorX8::new(&KEY);
+        let iv = [0u8; 16];
+
+        let plaintext = sample_data(8 * 16 + 3 * 16);
+        let mut buf = plaintext.clone();
+
+        cbc_encrypt(&single_enc, &iv, &mut buf);
+        assert_ne!(buf, plaintext);
+
+        cbc_decrypt(&single_dec, &x8_dec, &iv, &mut buf);
+        assert_eq!(buf, plaintext);
+    }
+
+    #[test]
+    fn cfb_decrypt_inverts_cfb_encrypt_with_a_partial_final_block() {
+        let single = AesSafe128Encryptor::new(&KEY);
+        let iv = [0u8; 16];
+
+        let plaintext = sample_data(2 * 16 + 5);
+        let mut buf = plaintext.clone();
+
+        cfb_encrypt(&single, &iv, &mut buf);
+        assert_ne!(buf, plaintext);
+
+        cfb_decrypt(&single, &iv, &mut buf);
+        assert_eq!(buf, plaintext);
+    }
+
+    #[test]
+    fn ofb_xor_is_its_own_inverse_with_a_partial_final_block() {
+        let single = AesSafe128Encryptor::new(&KEY);
+        let iv = [0u8; 16];
+
+        let plaintext = sample_data(2 * 16 + 5);
+        let mut buf = plaintext.clone();
+
+        ofb_xor(&single, &iv, &mut buf);
+        assert_ne!(buf, plaintext);
+
+        ofb_xor(&single, &iv, &mut buf);
+        assert_eq!(buf, plaintext);
+    }
+
+    #[test]
+    fn apply_dispatches_every_mode_consistently_with_its_direct_function() {
+        let single = Bidirectional {
+            enc: AesSafe128Encryptor::new(&KEY),
+            dec: AesSafe128Decryptor::new(&KEY),
+        };
+        let x8 = BidirectionalX8 {
+            enc: AesSafe128EncryptorX8::new(&KEY),
+            dec: AesSafe128DecryptorX8::new(&KEY),
+        };
+        let iv = [0u8; 16];
+
+        let plaintext = sample_data(8 * 16 + 3 * 16);
+
+        let mut via_apply = plaintext.clone();
+        apply(Mode::CbcEncrypt(&iv), &single, &x8, &mut via_apply);
+        let mut via_direct = plaintext.clone();
+        cbc_encrypt(&single, &iv, &mut via_direct);
+        assert_eq!(via_apply, via_direct);
+
+        apply(Mode::CbcDecrypt(&iv), &single, &x8, &mut via_apply);
+        assert_eq!(via_apply, plaintext);
+
+        let mut via_apply = plaintext.clone();
+        apply(Mode::Ctr(&iv), &single, &x8, &mut via_apply);
+        apply(Mode::Ctr(&iv), &single, &x8, &mut via_apply);
+        assert_eq!(via_apply, plaintext);
+
+        let mut via_apply = plaintext.clone();
+        apply(Mode::CfbEncrypt(&iv), &single, &x8, &mut via_apply);
+        apply(Mode::CfbDecrypt(&iv), &single, &x8, &mut via_apply);
+        assert_eq!(via_apply, plaintext);
+
+        let mut via_apply = plaintext.clone();
+        apply(Mode::Ofb(&iv), &single, &x8, &mut via_apply);
+        apply(Mode::Ofb(&iv), &single, &x8, &mut via_apply);
+        assert_eq!(via_apply, plaintext);
+    }
+}
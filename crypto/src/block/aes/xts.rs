@@ -0,0 +1,474 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! XTS-AES (IEEE 1619), a length-preserving, tweakable block-cipher mode built for encrypting
+//! fixed-size storage sectors/blocks without growing the ciphertext.
+//!
+//! `Xts<C>` is generic over any `C: BlockEncrypt<BlockSize = U16> + BlockDecrypt<BlockSize = U16>`
+//! - callers plug in whichever of this crate's AES front ends matches their key size
+//! (`aes::safe::AesSafe128Encryptor`-style pairs, or the dispatching `aes::aesni::Aes128`/`Aes192`/
+//! `Aes256`), so AES-128/192/256 all share this one mode implementation without XTS itself having
+//! to know anything about round-key layout.
+//!
+//! Each sector is encrypted independently: the sector index is encrypted under a second ("tweak")
+//! key to produce an initial 128-bit tweak, every 16-byte unit of the sector is XORed with the
+//! tweak before and after running the data cipher, and the tweak is advanced between units by
+//! multiplication by `x` in `GF(2^128)`. A sector whose length isn't a multiple of 16 bytes has
+//! its final partial unit folded into the preceding full unit via ciphertext stealing, so the
+//! output is always exactly as long as the input, for any length of at least 16 bytes.
+
+use block::{BlockDecrypt, BlockEncrypt};
+
+const UNIT_SIZE: usize = 16;
+
+fn xor_unit(block: &mut [u8], tweak: &[u8; UNIT_SIZE]) {
+    for (b, t) in block.iter_mut().zip(tweak.iter()) {
+        *b ^= *t;
+    }
+}
+
+// Multiplies a tweak, treated as a little-endian element of GF(2^128), by the primitive element
+// `x`: shift the 128-bit value left by one bit, and if a 1 bit carried out of the top, XOR the
+// reduction constant 0x87 into the new low byte.
+fn gf128_mul_x(tweak: &mut [u8; UNIT_SIZE]) {
+    let mut carry = 0u8;
+    for byte in tweak.iter_mut() {
+        let next_carry = *byte >> 7;
+        *byte = (*byte << 1) | carry;
+        carry = next_carry;
+    }
+    if carry != 0 {
+        tweak[0] ^= 0x87;
+    }
+}
+
+/// An XTS-AES cipher pair: `cipher` encrypts/decrypts the data, `tweak_cipher` (a second key of
+/// the same size) encrypts sector indices into initial tweaks.
+pub struct Xts<C> {
+    cipher: C,
+    tweak_cipher: C,
+}
+
+impl<C: BlockEncrypt + BlockDecrypt> Xts<C> {
+    pub fn new(cipher: C, tweak_cipher: C) -> Xts<C> {
+        Xts {
+            cipher: cipher,
+            tweak_cipher: tweak_cipher,
+        }
+    }
+
+    fn initial_tweak(&self, sector: u64) -> [u8; UNIT_SIZE] {
+        let mut sector_le = [0u8; UNIT_SIZE];
+        sector_le[..8].copy_from_slice(&sector.to_le_bytes_compat());
+
+        let mut tweak = [0u8; UNIT_SIZE];
+        self.tweak_cipher.encrypt_block(&sector_le[..], &mut tweak[..]);
+        tweak
+    }
+
+    /// Encrypts `data` in place as one XTS sector identified by `sector`. `data` must be at least
+    /// 16 bytes long; any length past that, including one that isn't a multiple of 16, is
+    /// supported via ciphertext stealing.
+    pub fn encrypt_sector(&self, sector: u64, data: &mut [u8]) {
+        assert!(data.len() >= UNIT_SIZE);
+
+        let mut tweak = self.initial_tweak(sector);
+        let full_units = data.len() / UNIT_SIZE;
+        let extra = data.len() % UNIT_SIZE;
+        let stolen = if extra == 0 { 0 } else { 1 };
+
+        for i in 0..full_units - stolen {
+            let block = &mut data[i * UNIT_SIZE..(i + 1) * UNIT_SIZE];
+            xor_unit(block, &tweak);
+            let mut out = [0u8; UNIT_SIZE];
+            self.cipher.encrypt_block(&block[..], &mut out[..]);
+            block.copy_from_slice(&out);
+            xor_unit(block, &tweak);
+            gf128_mul_x(&mut tweak);
+        }
+
+        if extra == 0 {
+            return;
+        }
+
+        // Ciphertext stealing: `tweak` now holds T_m, the tweak for the last full unit.
+        let m = full_units - 1;
+        let last_full_start = m * UNIT_SIZE;
+
+        let mut cc = [0u8; UNIT_SIZE];
+        cc.copy_from_slice(&data[last_full_start..last_full_start + UNIT_SIZE]);
+        xor_unit(&mut cc, &tweak);
+        let mut encrypted = [0u8; UNIT_SIZE];
+        self.cipher.encrypt_block(&cc[..], &mut encrypted[..]);
+        cc = encrypted;
+        xor_unit(&mut cc, &tweak);
+
+        // The merge block written in place of the last full unit takes the tweak one step past
+        // T_m, not T_m again: it stands in for a unit at position m+1, so it's XORed with T_{m+1}.
+        gf128_mul_x(&mut tweak);
+
+        let partial_start = last_full_start + UNIT_SIZE;
+        let mut stolen_unit = [0u8; UNIT_SIZE];
+        stolen_unit[..extra].copy_from_slice(&data[partial_start..partial_start + extra]);
+        stolen_unit[extra..].copy_from_slice(&cc[extra..]);
+
+        xor_unit(&mut stolen_unit, &tweak);
+        let mut final_full = [0u8; UNIT_SIZE];
+        self.cipher.encrypt_block(&stolen_unit[..], &mut final_full[..]);
+        xor_unit(&mut final_full, &tweak);
+
+        data[last_full_start..partial_start].copy_from_slice(&final_full);
+        data[partial_start..partial_start + extra].copy_from_slice(&cc[..extra]);
+    }
+
+    /// Decrypts `data` in place as one XTS sector identified by `sector` - the inverse of
+    /// `encrypt_sector`.
+    pub fn decrypt_sector(&self, sector: u64, data: &mut [u8]) {
+        assert!(data.len() >= UNIT_SIZE);
+
+        let mut tweak = self.initial_tweak(sector);
+        let full_units = data.len() / UNIT_SIZE;
+        let extra = data.len() % UNIT_SIZE;
+        let stolen = if extra == 0 { 0 } else { 1 };
+
+        for i in 0..full_units - stolen {
+            let block = &mut data[i * UNIT_SIZE..(i + 1) * UNIT_SIZE];
+            xor_unit(block, &tweak);
+            let mut out = [0u8; UNIT_SIZE];
+            self.cipher.decrypt_block(&block[..], &mut out[..]);
+            block.copy_from_slice(&out);
+            xor_unit(block, &tweak);
+            gf128_mul_x(&mut tweak);
+        }
+
+        if extra == 0 {
+            return;
+        }
+
+        // `tweak` now holds T_m, the tweak `encrypt_sector` used for the stolen full unit; the
+        // merge block it produced was XORed with T_{m+1} instead (see there), so it's decrypted
+        // with the same advanced tweak here.
+        let m = full_units - 1;
+        let last_full_start = m * UNIT_SIZE;
+        let partial_start = last_full_start + UNIT_SIZE;
+
+        let mut merge_tweak = tweak;
+        gf128_mul_x(&mut merge_tweak);
+
+        let mut received_full = [0u8; UNIT_SIZE];
+        received_full.copy_from_slice(&data[last_full_start..partial_start]);
+        xor_unit(&mut received_full, &merge_tweak);
+        let mut pp = [0u8; UNIT_SIZE];
+        self.cipher.decrypt_block(&received_full[..], &mut pp[..]);
+        xor_unit(&mut pp, &merge_tweak);
+
+        let mut cc = [0u8; UNIT_SIZE];
+        cc[..extra].copy_from_slice(&data[partial_start..partial_start + extra]);
+        cc[extra..].copy_from_slice(&pp[extra..]);
+
+        xor_unit(&mut cc, &tweak);
+        let mut recovered = [0u8; UNIT_SIZE];
+        self.cipher.decrypt_block(&cc[..], &mut recovered[..]);
+        xor_unit(&mut recovered, &tweak);
+
+        data[last_full_start..partial_start].copy_from_slice(&recovered);
+        data[partial_start..partial_start + extra].copy_from_slice(&pp[..extra]);
+    }
+}
+
+trait ToLeBytesCompat {
+    fn to_le_bytes_compat(self) -> [u8; 8];
+}
+
+impl ToLeBytesCompat for u64 {
+    fn to_le_bytes_compat(self) -> [u8; 8] {
+        let mut out = [0u8; 8];
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = (self >> (8 * i)) as u8;
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Xts;
+    use super::super::safe::{AesSafe128Decryptor, AesSafe128Encryptor};
+    use block::{BlockDecrypt, BlockEncrypt};
+
+    // A from-scratch, independently-written reference implementation of the XTS *construction*
+    // (tweak generation, GF(2^128) doubling, and ciphertext stealing), built on top of the same
+    // trusted `AesSafe128*` block cipher but deliberately not sharing any code with `Xts` itself.
+    // `gf128_mul_x` in `super` doubles the tweak via a byte-at-a-time shift; this reference instead
+    // treats the tweak as a little-endian pair of `u64` halves and doubles it with plain integer
+    // arithmetic, so the two can only agree if both the reduction polynomial and the byte ordering
+    // actually match. Differential testing against this catches exactly the class of bug a pure
+    // round-trip test can't: a wrong GF(2^128) polynomial, wrong endianness, or swapped XOR/stealing
+    // order that happens to still be self-consistent between `encrypt_sector` and `decrypt_sector`.
+    fn reference_gf128_double(tweak: [u8; 16]) -> [u8; 16] {
+        let mut lo = 0u64;
+        let mut hi = 0u64;
+        for i in 0..8 {
+            lo |= (tweak[i] as u64) << (8 * i);
+            hi |= (tweak[8 + i] as u64) << (8 * i);
+        }
+
+        let carry_out = hi >> 63;
+        let carry_into_hi = lo >> 63;
+        let mut new_lo = lo << 1;
+        let new_hi = (hi << 1) | carry_into_hi;
+        if carry_out == 1 {
+            new_lo ^= 0x87;
+        }
+
+        let mut out = [0u8; 16];
+        for i in 0..8 {
+            out[i] = (new_lo >> (8 * i)) as u8;
+            out[8 + i] = (new_hi >> (8 * i)) as u8;
+        }
+        out
+    }
+
+    fn reference_tweaks(tweak_cipher: &AesSafe128Encryptor, sector: u64, units: usize) -> Vec<[u8; 16]> {
+        let mut sector_le = [0u8; 16];
+        for i in 0..8 {
+            sector_le[i] = (sector >> (8 * i)) as u8;
+        }
+
+        let mut tweak = [0u8; 16];
+        tweak_cipher.encrypt_block(&sector_le[..], &mut tweak[..]);
+
+        let mut tweaks = Vec::with_capacity(units);
+        for _ in 0..units {
+            tweaks.push(tweak);
+            tweak = reference_gf128_double(tweak);
+        }
+        tweaks
+    }
+
+    fn reference_encrypt_sector(cipher: &AesSafe128Encryptor,
+                                 tweak_cipher: &AesSafe128Encryptor,
+                                 sector: u64,
+                                 plaintext: &[u8])
+                                 -> Vec<u8> {
+        let full_units = plaintext.len() / 16;
+        let extra = plaintext.len() % 16;
+        let units = if extra == 0 { full_units } else { full_units + 1 };
+        let tweaks = reference_tweaks(tweak_cipher, sector, units);
+
+        let mut blocks: Vec<[u8; 16]> = (0..full_units)
+            .map(|i| {
+                let mut block = [0u8; 16];
+                block.copy_from_slice(&plaintext[i * 16..(i + 1) * 16]);
+                for (b, t) in block.iter_mut().zip(tweaks[i].iter()) {
+                    *b ^= *t;
+                }
+                let mut out = [0u8; 16];
+                cipher.encrypt_block(&block[..], &mut out[..]);
+                for (o, t) in out.iter_mut().zip(tweaks[i].iter()) {
+                    *o ^= *t;
+                }
+                out
+            })
+            .collect();
+
+        let mut out = Vec::with_capacity(plaintext.len());
+        if extra == 0 {
+            for block in &blocks {
+                out.extend_from_slice(block);
+            }
+            return out;
+        }
+
+        // The merge block stands in for a unit one position past the last full one, so it's
+        // XORed with `tweaks[full_units]`, not the last full unit's own `tweaks[full_units - 1]`
+        // - matching the tweak advance `Xts::encrypt_sector` performs for the same step.
+        let stolen_from = blocks[full_units - 1];
+        let mut last = [0u8; 16];
+        last[..extra].copy_from_slice(&plaintext[full_units * 16..]);
+        last[extra..].copy_from_slice(&stolen_from[extra..]);
+        for (b, t) in last.iter_mut().zip(tweaks[full_units].iter()) {
+            *b ^= *t;
+        }
+        let mut last_out = [0u8; 16];
+        cipher.encrypt_block(&last[..], &mut last_out[..]);
+        for (o, t) in last_out.iter_mut().zip(tweaks[full_units].iter()) {
+            *o ^= *t;
+        }
+        blocks[full_units - 1] = last_out;
+
+        for i in 0..full_units - 1 {
+            out.extend_from_slice(&blocks[i]);
+        }
+        out.extend_from_slice(&blocks[full_units - 1]);
+        out.extend_from_slice(&stolen_from[..extra]);
+        out
+    }
+
+    #[test]
+    fn agrees_with_an_independently_implemented_reference_for_whole_sectors() {
+        let cipher = AesSafe128Encryptor::new(b"0123456789abcdef");
+        let tweak_cipher = AesSafe128Encryptor::new(b"fedcba9876543210");
+        let xts = Xts::new(AesSafe128Encryptor::new(b"0123456789abcdef"), tweak_cipher.clone());
+
+        let plaintext: Vec<u8> = (0..80).map(|i| i as u8).collect();
+        let mut ciphertext = plaintext.clone();
+        xts.encrypt_sector(42, &mut ciphertext);
+
+        let expected = reference_encrypt_sector(&cipher, &tweak_cipher, 42, &plaintext);
+        assert_eq!(ciphertext, expected);
+    }
+
+    #[test]
+    fn agrees_with_an_independently_implemented_reference_with_ciphertext_stealing() {
+        let cipher = AesSafe128Encryptor::new(b"0123456789abcdef");
+        let tweak_cipher = AesSafe128Encryptor::new(b"fedcba9876543210");
+
+        for len in 17..48usize {
+            let xts = Xts::new(AesSafe128Encryptor::new(b"0123456789abcdef"), tweak_cipher.clone());
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+
+            let mut ciphertext = plaintext.clone();
+            xts.encrypt_sector(7, &mut ciphertext);
+
+            let expected = reference_encrypt_sector(&cipher, &tweak_cipher, 7, &plaintext);
+            assert_eq!(ciphertext, expected, "mismatch for sector length {}", len);
+        }
+    }
+
+    #[test]
+    fn decrypt_inverts_the_reference_encryption() {
+        let tweak_cipher = AesSafe128Encryptor::new(b"fedcba9876543210");
+        let dec = Xts::new(AesSafe128Decryptor::new(b"0123456789abcdef"), tweak_cipher.clone());
+
+        for len in &[16usize, 17, 31, 32, 37, 48] {
+            let cipher = AesSafe128Encryptor::new(b"0123456789abcdef");
+            let plaintext: Vec<u8> = (0..*len).map(|i| (i * 3) as u8).collect();
+            let mut ciphertext = reference_encrypt_sector(&cipher, &tweak_cipher, 9, &plaintext);
+
+            dec.decrypt_sector(9, &mut ciphertext);
+            assert_eq!(ciphertext, plaintext, "mismatch for sector length {}", len);
+        }
+    }
+
+    // Known-answer vectors for the exact keys/sector/plaintext combinations used elsewhere in this
+    // file, computed with Python's `cryptography` package (OpenSSL's FIPS-validated AES-XTS
+    // implementation) as an independent, widely-deployed reference - not transcribed from memory,
+    // so they can't suffer from the same misremembered-byte risk a hand-copied published vector
+    // would. Anyone can regenerate them with `cryptography.hazmat.primitives.ciphers.modes.XTS`.
+    const KAT_WHOLE_CIPHERTEXT: [u8; 80] =
+        [0x8c, 0xc7, 0x75, 0x47, 0xb4, 0xb1, 0x93, 0x2c, 0x9c, 0xf9, 0x73, 0xdd, 0x65, 0xb7, 0xde,
+         0xf2, 0xcb, 0xf3, 0xf9, 0xf7, 0x42, 0xd6, 0xcd, 0xa3, 0xae, 0xeb, 0x5a, 0x86, 0x77, 0x43,
+         0x4f, 0x9c, 0xe2, 0x44, 0xb8, 0xcf, 0xc2, 0x62, 0x00, 0x90, 0xd5, 0x9d, 0x36, 0xbd, 0x10,
+         0x24, 0x4d, 0x26, 0x3a, 0xa1, 0xb6, 0xaf, 0x70, 0x88, 0x8a, 0x3f, 0xec, 0x18, 0x94, 0x6d,
+         0x25, 0x87, 0x15, 0x82, 0x3e, 0x7e, 0x18, 0xbc, 0x72, 0xc4, 0x2f, 0x6f, 0x50, 0xcf, 0x46,
+         0x74, 0x0a, 0xf8, 0xf2, 0xa6];
+
+    const KAT_STEALING_CIPHERTEXT: [u8; 37] =
+        [0x90, 0xa4, 0xf9, 0x77, 0x79, 0xc4, 0x73, 0x2e, 0x17, 0xc5, 0xc9, 0xc9, 0x7c, 0x75, 0xd6,
+         0x86, 0xcd, 0x6f, 0x4c, 0x6e, 0x02, 0xf6, 0xc4, 0x48, 0xfe, 0x16, 0xb1, 0xe4, 0x0c, 0x46,
+         0x34, 0x6e, 0x20, 0xe6, 0x44, 0x0e, 0xb5];
+
+    const KAT_SIMPLE_CIPHERTEXT: [u8; 32] =
+        [0x9b, 0xcd, 0x80, 0x9a, 0x15, 0xd1, 0x53, 0x37, 0xe0, 0x8b, 0x4f, 0x61, 0xc6, 0x92, 0x38,
+         0xfb, 0xbd, 0x40, 0x1e, 0x2a, 0x12, 0x1c, 0x4e, 0x80, 0xf2, 0xab, 0x5d, 0x0a, 0x86, 0xdb,
+         0xd1, 0xe7];
+
+    #[test]
+    fn matches_known_answer_vector_for_a_whole_sector() {
+        let xts = Xts::new(AesSafe128Encryptor::new(b"0123456789abcdef"),
+                            AesSafe128Encryptor::new(b"fedcba9876543210"));
+        let plaintext: Vec<u8> = (0..80).map(|i| i as u8).collect();
+
+        let mut ciphertext = plaintext.clone();
+        xts.encrypt_sector(42, &mut ciphertext);
+        assert_eq!(&ciphertext[..], &KAT_WHOLE_CIPHERTEXT[..]);
+
+        let dec = Xts::new(AesSafe128Decryptor::new(b"0123456789abcdef"),
+                            AesSafe128Encryptor::new(b"fedcba9876543210"));
+        dec.decrypt_sector(42, &mut ciphertext);
+        assert_eq!(ciphertext, plaintext);
+    }
+
+    #[test]
+    fn matches_known_answer_vector_with_ciphertext_stealing() {
+        let xts = Xts::new(AesSafe128Encryptor::new(b"0123456789abcdef"),
+                            AesSafe128Encryptor::new(b"fedcba9876543210"));
+        let plaintext: Vec<u8> = (0..37).map(|i| i as u8).collect();
+
+        let mut ciphertext = plaintext.clone();
+        xts.encrypt_sector(3, &mut ciphertext);
+        assert_eq!(&ciphertext[..], &KAT_STEALING_CIPHERTEXT[..]);
+
+        let dec = Xts::new(AesSafe128Decryptor::new(b"0123456789abcdef"),
+                            AesSafe128Encryptor::new(b"fedcba9876543210"));
+        dec.decrypt_sector(3, &mut ciphertext);
+        assert_eq!(ciphertext, plaintext);
+    }
+
+    #[test]
+    fn matches_known_answer_vector_with_distinct_zero_based_keys() {
+        let key = [0u8; 16];
+        let tweak_key: [u8; 16] = [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a,
+                                    0x0b, 0x0c, 0x0d, 0x0e, 0x0f];
+
+        let xts = Xts::new(AesSafe128Encryptor::new(&key), AesSafe128Encryptor::new(&tweak_key));
+        let mut data = [0u8; 32];
+        xts.encrypt_sector(0, &mut data);
+        assert_eq!(data, KAT_SIMPLE_CIPHERTEXT);
+
+        let dec = Xts::new(AesSafe128Decryptor::new(&key), AesSafe128Encryptor::new(&tweak_key));
+        dec.decrypt_sector(0, &mut data);
+        assert_eq!(data, [0u8; 32]);
+    }
+
+    fn xts_pair(key: &[u8], tweak_key: &[u8]) -> (Xts<AesSafe128Encryptor>, Xts<AesSafe128Decryptor>) {
+        (Xts::new(AesSafe128Encryptor::new(key), AesSafe128Encryptor::new(tweak_key)),
+         Xts::new(AesSafe128Decryptor::new(key), AesSafe128Encryptor::new(tweak_key)))
+    }
+
+    #[test]
+    fn round_trips_whole_sectors() {
+        let (enc, dec) = xts_pair(b"0123456789abcdef", b"fedcba9876543210");
+        let plaintext = [0x42u8; 512];
+
+        let mut ciphertext = plaintext;
+        enc.encrypt_sector(7, &mut ciphertext);
+        assert_ne!(&ciphertext[..], &plaintext[..]);
+
+        let mut recovered = ciphertext;
+        dec.decrypt_sector(7, &mut recovered);
+        assert_eq!(&recovered[..], &plaintext[..]);
+    }
+
+    #[test]
+    fn round_trips_with_ciphertext_stealing() {
+        let (enc, dec) = xts_pair(b"0123456789abcdef", b"fedcba9876543210");
+        let plaintext: Vec<u8> = (0..37).collect();
+
+        let mut ciphertext = plaintext.clone();
+        enc.encrypt_sector(3, &mut ciphertext);
+        assert_eq!(ciphertext.len(), plaintext.len());
+        assert_ne!(ciphertext, plaintext);
+
+        let mut recovered = ciphertext;
+        dec.decrypt_sector(3, &mut recovered);
+        assert_eq!(recovered, plaintext);
+    }
+
+    #[test]
+    fn sector_index_changes_the_ciphertext() {
+        let (enc, _) = xts_pair(b"0123456789abcdef", b"fedcba9876543210");
+        let plaintext = [0x11u8; 32];
+
+        let mut a = plaintext;
+        enc.encrypt_sector(0, &mut a);
+        let mut b = plaintext;
+        enc.encrypt_sector(1, &mut b);
+
+        assert_ne!(&a[..], &b[..]);
+    }
+}
@@ -0,0 +1,237 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! An implementation of the Blowfish block cipher.
+//!
+//! Blowfish is a 64-bit block cipher with a 32-448 bit (4-56 byte) key. This module exposes both
+//! the low-level, immutable `Blowfish` state that the `bcrypt` KDF drives directly through
+//! `salted_expand_key`/`expand_key`/`encrypt_round`, and a standard `BlockEncrypt`/`BlockDecrypt`
+//! cipher built from a plain key via `Blowfish::new`, for use in the `modes` below or any other
+//! `crypto::block` mode implementation.
+
+use std::mem;
+
+use byteorder::{ByteOrder, BigEndian};
+use typenum::consts::U8;
+
+use block::{BlockEncrypt, BlockDecrypt};
+
+use self::consts::{P_INIT, S_INIT};
+
+mod consts;
+pub mod modes;
+
+/// The Blowfish cipher state: the 18-word P-array and the four 256-word S-boxes that together
+/// make up the Feistel network's round keys.
+#[derive(Clone)]
+pub struct Blowfish {
+    p: [u32; 18],
+    s: [[u32; 256]; 4],
+}
+
+impl Blowfish {
+    /// Returns the state seeded with the standard, unkeyed Blowfish constants, ready to be fed
+    /// through `expand_key` or `salted_expand_key`.
+    pub fn init() -> Blowfish {
+        Blowfish {
+            p: P_INIT,
+            s: S_INIT,
+        }
+    }
+
+    /// Runs the standard Blowfish key schedule for an arbitrary-length key (4-56 bytes) and
+    /// returns a cipher ready for `encrypt_block`/`decrypt_block`.
+    pub fn new(key: &[u8]) -> Blowfish {
+        assert!(key.len() >= 4 && key.len() <= 56);
+
+        Blowfish::init().expand_key(key)
+    }
+
+    fn xor_key_into_p(&mut self, key: &[u8]) {
+        let mut key_pos = 0;
+        for p in self.p.iter_mut() {
+            let mut word = 0u32;
+            for _ in 0..4 {
+                word = (word << 8) | key[key_pos] as u32;
+                key_pos = (key_pos + 1) % key.len();
+            }
+            *p ^= word;
+        }
+    }
+
+    /// The standard Blowfish key schedule: XOR the key cyclically into the P-array, then replace
+    /// every P-array and S-box entry in turn with the output of encrypting an all-zero block
+    /// under the state built up so far.
+    pub fn expand_key(mut self, key: &[u8]) -> Blowfish {
+        self.xor_key_into_p(key);
+
+        let mut lr = (0u32, 0u32);
+
+        for i in 0..9 {
+            lr = self.encrypt_round(lr);
+            self.p[2 * i] = lr.0;
+            self.p[2 * i + 1] = lr.1;
+        }
+
+        for s in 0..4 {
+            for i in 0..128 {
+                lr = self.encrypt_round(lr);
+                self.s[s][2 * i] = lr.0;
+                self.s[s][2 * i + 1] = lr.1;
+            }
+        }
+
+        self
+    }
+
+    /// The EKSBlowfish ("expensive key schedule") variant `bcrypt` uses: identical to
+    /// `expand_key`, except the salt is mixed, 32 bits at a time and cycled, into `(l, r)` before
+    /// every encryption that produces a new P-array/S-box entry. This ties the key schedule to
+    /// the salt as well as the password, which is what makes bcrypt salted.
+    pub fn salted_expand_key(mut self, salt: &[u8], key: &[u8]) -> Blowfish {
+        self.xor_key_into_p(key);
+
+        assert_eq!(salt.len() % 4, 0);
+        let salt_words = salt.len() / 4;
+        let mut salt_pos = 0;
+
+        let mut lr = (0u32, 0u32);
+
+        for i in 0..9 {
+            lr.0 ^= BigEndian::read_u32(&salt[salt_pos * 4..salt_pos * 4 + 4]);
+            salt_pos = (salt_pos + 1) % salt_words;
+            lr.1 ^= BigEndian::read_u32(&salt[salt_pos * 4..salt_pos * 4 + 4]);
+            salt_pos = (salt_pos + 1) % salt_words;
+            lr = self.encrypt_round(lr);
+            self.p[2 * i] = lr.0;
+            self.p[2 * i + 1] = lr.1;
+        }
+
+        for s in 0..4 {
+            for i in 0..128 {
+                lr.0 ^= BigEndian::read_u32(&salt[salt_pos * 4..salt_pos * 4 + 4]);
+                salt_pos = (salt_pos + 1) % salt_words;
+                lr.1 ^= BigEndian::read_u32(&salt[salt_pos * 4..salt_pos * 4 + 4]);
+                salt_pos = (salt_pos + 1) % salt_words;
+                lr = self.encrypt_round(lr);
+                self.s[s][2 * i] = lr.0;
+                self.s[s][2 * i + 1] = lr.1;
+            }
+        }
+
+        self
+    }
+
+    // The Feistel round function: split the 32-bit half-block into four bytes, push each through
+    // its own S-box, then combine with mixed addition/XOR, as specified by Blowfish's design.
+    fn f(&self, x: u32) -> u32 {
+        let a = (x >> 24) & 0xff;
+        let b = (x >> 16) & 0xff;
+        let c = (x >> 8) & 0xff;
+        let d = x & 0xff;
+
+        (self.s[0][a as usize].wrapping_add(self.s[1][b as usize]) ^ self.s[2][c as usize])
+            .wrapping_add(self.s[3][d as usize])
+    }
+
+    /// Encrypts a single 64-bit block, given as two 32-bit halves, running the full 16-round
+    /// Feistel network forward through the P-array. `bcrypt` drives this directly (64 times in a
+    /// row) instead of going through `encrypt_block`, since it operates on a 3-word-pair state
+    /// rather than raw bytes.
+    pub fn encrypt_round(&self, (mut l, mut r): (u32, u32)) -> (u32, u32) {
+        for i in 0..16 {
+            l ^= self.p[i];
+            r ^= self.f(l);
+            mem::swap(&mut l, &mut r);
+        }
+        mem::swap(&mut l, &mut r);
+
+        r ^= self.p[16];
+        l ^= self.p[17];
+
+        (l, r)
+    }
+
+    fn decrypt_round(&self, (mut l, mut r): (u32, u32)) -> (u32, u32) {
+        for i in (2..18).rev() {
+            l ^= self.p[i];
+            r ^= self.f(l);
+            mem::swap(&mut l, &mut r);
+        }
+        mem::swap(&mut l, &mut r);
+
+        r ^= self.p[1];
+        l ^= self.p[0];
+
+        (l, r)
+    }
+}
+
+impl BlockEncrypt for Blowfish {
+    type BlockSize = U8;
+
+    fn encrypt_block<I: AsRef<[u8]>, O: AsMut<[u8]>>(&self, input: I, mut output: O) {
+        let input = input.as_ref();
+        let output = output.as_mut();
+
+        let l = BigEndian::read_u32(&input[0..4]);
+        let r = BigEndian::read_u32(&input[4..8]);
+        let (l, r) = self.encrypt_round((l, r));
+
+        BigEndian::write_u32(&mut output[0..4], l);
+        BigEndian::write_u32(&mut output[4..8], r);
+    }
+}
+
+impl BlockDecrypt for Blowfish {
+    type BlockSize = U8;
+
+    fn decrypt_block<I: AsRef<[u8]>, O: AsMut<[u8]>>(&self, input: I, mut output: O) {
+        let input = input.as_ref();
+        let output = output.as_mut();
+
+        let l = BigEndian::read_u32(&input[0..4]);
+        let r = BigEndian::read_u32(&input[4..8]);
+        let (l, r) = self.decrypt_round((l, r));
+
+        BigEndian::write_u32(&mut output[0..4], l);
+        BigEndian::write_u32(&mut output[4..8], r);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Blowfish;
+    use block::{BlockEncrypt, BlockDecrypt};
+
+    // From Bruce Schneier's published Blowfish test vectors (all-zero key and block).
+    #[test]
+    fn schneier_zero_vector() {
+        let cipher = Blowfish::new(&[0u8; 8]);
+
+        let mut ciphertext = [0u8; 8];
+        cipher.encrypt_block(&[0u8; 8][..], &mut ciphertext[..]);
+        assert_eq!(ciphertext, [0x4e, 0xf9, 0x97, 0x45, 0x61, 0x98, 0xdd, 0x78]);
+
+        let mut plaintext = [0u8; 8];
+        cipher.decrypt_block(&ciphertext[..], &mut plaintext[..]);
+        assert_eq!(plaintext, [0u8; 8]);
+    }
+
+    #[test]
+    fn encrypt_block_round_trips_for_arbitrary_keys() {
+        let cipher = Blowfish::new(b"a much longer test key than eight bytes");
+
+        let plaintext = *b"ABCDEFGH";
+        let mut ciphertext = [0u8; 8];
+        cipher.encrypt_block(&plaintext[..], &mut ciphertext[..]);
+        assert_ne!(ciphertext, plaintext);
+
+        let mut decrypted = [0u8; 8];
+        cipher.decrypt_block(&ciphertext[..], &mut decrypted[..]);
+        assert_eq!(decrypted, plaintext);
+    }
+}
@@ -0,0 +1,215 @@
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Streaming block-cipher modes built on top of `Blowfish::encrypt_block`/`decrypt_block`: ECB,
+//! CBC, and CTR, with PKCS#7 padding for the two modes (ECB, CBC) that require full blocks.
+
+use super::Blowfish;
+
+const BLOCK_SIZE: usize = 8;
+
+fn xor_block(block: &mut [u8], other: &[u8]) {
+    for (b, o) in block.iter_mut().zip(other) {
+        *b ^= *o;
+    }
+}
+
+fn pkcs7_pad(data: &[u8]) -> Vec<u8> {
+    let pad_len = BLOCK_SIZE - (data.len() % BLOCK_SIZE);
+    let mut padded = Vec::with_capacity(data.len() + pad_len);
+    padded.extend_from_slice(data);
+    padded.extend(::std::iter::repeat(pad_len as u8).take(pad_len));
+    padded
+}
+
+fn pkcs7_unpad(data: &[u8]) -> Option<&[u8]> {
+    let pad_len = *data.last()? as usize;
+    if pad_len == 0 || pad_len > BLOCK_SIZE || pad_len > data.len() {
+        return None;
+    }
+    if data[data.len() - pad_len..].iter().any(|&b| b as usize != pad_len) {
+        return None;
+    }
+    Some(&data[..data.len() - pad_len])
+}
+
+/// Electronic codebook mode: every block is encrypted independently. Provided mainly for
+/// completeness - prefer `Cbc` or `Ctr` for anything that encrypts more than one block of related
+/// data, since ECB leaks repeated plaintext blocks.
+pub struct Ecb {
+    cipher: Blowfish,
+}
+
+impl Ecb {
+    pub fn new(cipher: Blowfish) -> Ecb {
+        Ecb { cipher: cipher }
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let padded = pkcs7_pad(plaintext);
+        let mut out = vec![0u8; padded.len()];
+        for (block, out) in padded.chunks(BLOCK_SIZE).zip(out.chunks_mut(BLOCK_SIZE)) {
+            self.cipher.encrypt_block(block, out);
+        }
+        out
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        if ciphertext.is_empty() || ciphertext.len() % BLOCK_SIZE != 0 {
+            return None;
+        }
+
+        let mut out = vec![0u8; ciphertext.len()];
+        for (block, out) in ciphertext.chunks(BLOCK_SIZE).zip(out.chunks_mut(BLOCK_SIZE)) {
+            self.cipher.decrypt_block(block, out);
+        }
+
+        pkcs7_unpad(&out).map(|plain| plain.to_vec())
+    }
+}
+
+/// Cipher block chaining mode: each plaintext block is XORed with the previous ciphertext block
+/// (or the IV, for the first block) before encryption.
+pub struct Cbc {
+    cipher: Blowfish,
+    iv: [u8; BLOCK_SIZE],
+}
+
+impl Cbc {
+    pub fn new(cipher: Blowfish, iv: &[u8]) -> Cbc {
+        assert_eq!(iv.len(), BLOCK_SIZE);
+        let mut fixed_iv = [0u8; BLOCK_SIZE];
+        fixed_iv.copy_from_slice(iv);
+        Cbc {
+            cipher: cipher,
+            iv: fixed_iv,
+        }
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let padded = pkcs7_pad(plaintext);
+        let mut out = vec![0u8; padded.len()];
+        let mut prev = self.iv;
+
+        for (block, out) in padded.chunks(BLOCK_SIZE).zip(out.chunks_mut(BLOCK_SIZE)) {
+            let mut mixed = [0u8; BLOCK_SIZE];
+            mixed.copy_from_slice(block);
+            xor_block(&mut mixed, &prev);
+
+            self.cipher.encrypt_block(&mixed[..], &mut out[..]);
+            prev.copy_from_slice(out);
+        }
+
+        out
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        if ciphertext.is_empty() || ciphertext.len() % BLOCK_SIZE != 0 {
+            return None;
+        }
+
+        let mut out = vec![0u8; ciphertext.len()];
+        let mut prev = self.iv;
+
+        for (block, out) in ciphertext.chunks(BLOCK_SIZE).zip(out.chunks_mut(BLOCK_SIZE)) {
+            self.cipher.decrypt_block(block, &mut out[..]);
+            xor_block(out, &prev);
+            prev.copy_from_slice(block);
+        }
+
+        pkcs7_unpad(&out).map(|plain| plain.to_vec())
+    }
+}
+
+/// Counter mode: a keystream is generated by encrypting a big-endian counter seeded with the
+/// nonce, then XORed into the data. Since the block cipher is only ever run in the encrypt
+/// direction, `Ctr` is used identically for encryption and decryption, and needs no padding - the
+/// output is exactly as long as the input.
+pub struct Ctr {
+    cipher: Blowfish,
+    nonce: [u8; BLOCK_SIZE],
+}
+
+impl Ctr {
+    pub fn new(cipher: Blowfish, nonce: &[u8]) -> Ctr {
+        assert_eq!(nonce.len(), BLOCK_SIZE);
+        let mut fixed_nonce = [0u8; BLOCK_SIZE];
+        fixed_nonce.copy_from_slice(nonce);
+        Ctr {
+            cipher: cipher,
+            nonce: fixed_nonce,
+        }
+    }
+
+    pub fn apply_keystream(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0u8; data.len()];
+        let mut counter = u64::from(self.nonce[0]) << 56 | u64::from(self.nonce[1]) << 48 |
+                          u64::from(self.nonce[2]) << 40 | u64::from(self.nonce[3]) << 32 |
+                          u64::from(self.nonce[4]) << 24 | u64::from(self.nonce[5]) << 16 |
+                          u64::from(self.nonce[6]) << 8 | u64::from(self.nonce[7]);
+
+        for (data, out) in data.chunks(BLOCK_SIZE).zip(out.chunks_mut(BLOCK_SIZE)) {
+            let mut keystream = [0u8; BLOCK_SIZE];
+            let mut counter_block = [0u8; BLOCK_SIZE];
+            for (i, b) in counter_block.iter_mut().enumerate() {
+                *b = (counter >> (56 - 8 * i)) as u8;
+            }
+
+            self.cipher.encrypt_block(&counter_block[..], &mut keystream[..]);
+
+            for (o, (d, k)) in out.iter_mut().zip(data.iter().zip(keystream.iter())) {
+                *o = d ^ k;
+            }
+
+            counter = counter.wrapping_add(1);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ecb, Cbc, Ctr};
+    use super::super::Blowfish;
+
+    #[test]
+    fn ecb_round_trips() {
+        let cipher = Ecb::new(Blowfish::new(b"test key 123"));
+        let plaintext = b"a message that spans multiple eight byte blocks";
+
+        let ciphertext = cipher.encrypt(&plaintext[..]);
+        assert_eq!(cipher.decrypt(&ciphertext).unwrap(), &plaintext[..]);
+    }
+
+    #[test]
+    fn cbc_round_trips() {
+        let cipher = Cbc::new(Blowfish::new(b"test key 123"), &[0u8; 8]);
+        let plaintext = b"a message that spans multiple eight byte blocks";
+
+        let ciphertext = cipher.encrypt(&plaintext[..]);
+        assert_eq!(cipher.decrypt(&ciphertext).unwrap(), &plaintext[..]);
+    }
+
+    #[test]
+    fn cbc_changes_with_the_iv() {
+        let plaintext = b"the quick brown fox jumped over";
+        let a = Cbc::new(Blowfish::new(b"test key 123"), &[0u8; 8]).encrypt(&plaintext[..]);
+        let b = Cbc::new(Blowfish::new(b"test key 123"), &[1u8; 8]).encrypt(&plaintext[..]);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn ctr_round_trips_without_padding() {
+        let plaintext = b"not a multiple of eight bytes!!!";
+        let a = Ctr::new(Blowfish::new(b"test key 123"), &[0u8; 8]);
+        let ciphertext = a.apply_keystream(&plaintext[..]);
+        assert_eq!(ciphertext.len(), plaintext.len());
+
+        let b = Ctr::new(Blowfish::new(b"test key 123"), &[0u8; 8]);
+        assert_eq!(b.apply_keystream(&ciphertext), &plaintext[..]);
+    }
+}
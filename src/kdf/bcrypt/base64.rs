@@ -0,0 +1,109 @@
+//! bcrypt's own radix-64 encoding: the same big-endian bit packing as standard base64, but with a
+//! different, unpadded alphabet. This is only used to render/parse the modular crypt format in
+//! `super`, so the helpers here stay private to the `bcrypt` module.
+
+const ALPHABET: &'static [u8; 64] =
+    b"./ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+pub fn encode(input: &[u8]) -> String {
+    let mut out = Vec::with_capacity((input.len() * 4 + 2) / 3);
+
+    for chunk in input.chunks(3) {
+        let b0 = chunk[0];
+        out.push(ALPHABET[(b0 >> 2) as usize]);
+
+        let mut acc = (b0 & 0x03) << 4;
+        if chunk.len() == 1 {
+            out.push(ALPHABET[acc as usize]);
+            continue;
+        }
+
+        let b1 = chunk[1];
+        acc |= b1 >> 4;
+        out.push(ALPHABET[acc as usize]);
+
+        acc = (b1 & 0x0f) << 2;
+        if chunk.len() == 2 {
+            out.push(ALPHABET[acc as usize]);
+            continue;
+        }
+
+        let b2 = chunk[2];
+        acc |= b2 >> 6;
+        out.push(ALPHABET[acc as usize]);
+        out.push(ALPHABET[(b2 & 0x3f) as usize]);
+    }
+
+    String::from_utf8(out).expect("bcrypt base64 alphabet is ASCII")
+}
+
+fn index_of(byte: u8) -> Option<u8> {
+    ALPHABET.iter().position(|&b| b == byte).map(|i| i as u8)
+}
+
+/// Decodes exactly `output.len()` bytes from `input`, consuming as many radix-64 characters as
+/// that requires. Returns `false` if `input` runs out of characters or contains a byte outside
+/// bcrypt's alphabet.
+pub fn decode(input: &str, output: &mut [u8]) -> bool {
+    let chars: Vec<u8> = input.bytes().collect();
+    let mut pos = 0;
+    let mut i = 0;
+
+    macro_rules! next {
+        () => {
+            match chars.get(i).cloned().and_then(index_of) {
+                Some(v) => {
+                    i += 1;
+                    v
+                }
+                None => return false,
+            }
+        }
+    }
+
+    while pos < output.len() {
+        let c0 = next!();
+        let c1 = next!();
+        output[pos] = (c0 << 2) | (c1 >> 4);
+        pos += 1;
+        if pos == output.len() {
+            break;
+        }
+
+        let c2 = next!();
+        output[pos] = (c1 << 4) | (c2 >> 2);
+        pos += 1;
+        if pos == output.len() {
+            break;
+        }
+
+        let c3 = next!();
+        output[pos] = (c2 << 6) | c3;
+        pos += 1;
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode, decode};
+
+    #[test]
+    fn round_trips_arbitrary_lengths() {
+        for len in 1..40 {
+            let input: Vec<u8> = (0..len as u8).collect();
+            let encoded = encode(&input);
+
+            let mut output = vec![0u8; len];
+            assert!(decode(&encoded, &mut output));
+            assert_eq!(output, input);
+        }
+    }
+
+    #[test]
+    fn rejects_bytes_outside_the_alphabet() {
+        let mut output = [0u8; 1];
+        assert!(!decode("!!", &mut output));
+    }
+}
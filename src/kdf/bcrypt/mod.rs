@@ -1,7 +1,95 @@
-use byteorder::{ByteOrder, BigEndian};
+use std::str;
+
+use byteorder::{ByteOrder, BigEndian, LittleEndian};
+use rand::{Rng, OsRng};
+
+use digest::Digest;
+use digest::sha2::Sha512;
 
 use crypto::block::blowfish::Blowfish;
 
+mod base64;
+
+/// The magic constant bcrypt_pbkdf enciphers in place of the OpenBSD `bcrypt` constant, taken
+/// verbatim (and just as arbitrarily) from the OpenSSH implementation this KDF is compatible with.
+const PBKDF_MAGIC: &'static [u8; 32] = b"OxychromaticBlowfishSwatDynamite";
+
+fn sha512(parts: &[&[u8]]) -> [u8; 64] {
+    let mut hasher = Sha512::default();
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut out = [0u8; 64];
+    hasher.result(&mut out);
+    out
+}
+
+// The inner bcrypt round function used by bcrypt_pbkdf: expands the Blowfish state from a
+// pre-hashed password/salt pair and runs it over the same magic constant the raw `bcrypt`
+// function above runs over "OrpheanBeholderScryDoubt".
+fn bcrypt_hash(hpass: &[u8; 64], hsalt: &[u8; 64]) -> [u8; 32] {
+    let mut state = Blowfish::init().salted_expand_key(&hsalt[..], &hpass[..]);
+
+    for _ in 0..64 {
+        state = state.expand_key(&hsalt[..]).expand_key(&hpass[..]);
+    }
+
+    let mut ctext = [0u32; 8];
+    for (word, chunk) in ctext.iter_mut().zip(PBKDF_MAGIC.chunks(4)) {
+        *word = BigEndian::read_u32(chunk);
+    }
+
+    for lr in ctext.chunks_mut(2) {
+        for _ in 0..64 {
+            let (l, r) = state.encrypt_round((lr[0], lr[1]));
+            lr[0] = l;
+            lr[1] = r;
+        }
+    }
+
+    let mut out = [0u8; 32];
+    for (word, chunk) in ctext.iter().zip(out.chunks_mut(4)) {
+        LittleEndian::write_u32(chunk, *word);
+    }
+    out
+}
+
+/// Derives an arbitrary-length key from a password and salt using the `bcrypt_pbkdf` construction
+/// OpenSSH uses to encrypt private keys: it rehashes the password/salt with SHA-512 and feeds the
+/// result through the bcrypt round function above instead of HMAC, which keeps key derivation tied
+/// to the same tunable, memory-hungry primitive `bcrypt` already uses for password hashing.
+pub fn bcrypt_pbkdf(password: &[u8], salt: &[u8], rounds: u32, output: &mut [u8]) {
+    assert!(!password.is_empty());
+    assert!(!salt.is_empty());
+    assert!(rounds > 0);
+
+    let hpass = sha512(&[password]);
+    let nblocks = (output.len() + 31) / 32;
+
+    for block in 1..(nblocks + 1) {
+        let mut be_block = [0u8; 4];
+        BigEndian::write_u32(&mut be_block, block as u32);
+
+        let mut hsalt = sha512(&[salt, &be_block]);
+        let mut out = bcrypt_hash(&hpass, &hsalt);
+
+        for _ in 2..(rounds + 1) {
+            hsalt = sha512(&[&out]);
+            let tmp = bcrypt_hash(&hpass, &hsalt);
+            for (o, t) in out.iter_mut().zip(tmp.iter()) {
+                *o ^= t;
+            }
+        }
+
+        for (i, byte) in out.iter().enumerate() {
+            let idx = i * nblocks + (block - 1);
+            if idx < output.len() {
+                output[idx] = *byte;
+            }
+        }
+    }
+}
+
 fn bcrypt_setup(cost: usize, salt: &[u8], key: &[u8]) -> Blowfish {
     let mut state = Blowfish::init().salted_expand_key(salt, key);
 
@@ -12,6 +100,22 @@ fn bcrypt_setup(cost: usize, salt: &[u8], key: &[u8]) -> Blowfish {
     state
 }
 
+// Enciphers the fixed "OrpheanBeholderScryDoubt" constant 64 times under an already-expanded
+// Blowfish state, writing the 24-byte result. Shared by `bcrypt` and `bcrypt_batch` so the two
+// stay bit-for-bit identical.
+fn bcrypt_ctext(state: &Blowfish, output: &mut [u8]) {
+    let mut ctext = [0x4f727068, 0x65616e42, 0x65686f6c, 0x64657253, 0x63727944, 0x6f756274];
+    for (chunk, out) in ctext.chunks_mut(2).zip(output.chunks_mut(8)) {
+        for _ in 0..64 {
+            let (l, r) = state.encrypt_round((chunk[0], chunk[1]));
+            chunk[0] = l;
+            chunk[1] = r;
+        }
+        BigEndian::write_u32(&mut out[0..4], chunk[0]);
+        BigEndian::write_u32(&mut out[4..8], chunk[1]);
+    }
+}
+
 pub fn bcrypt<S: AsRef<[u8]>, I: AsRef<[u8]>, O: AsMut<[u8]>>(cost: usize,
                                                               salt: S,
                                                               input: I,
@@ -20,21 +124,156 @@ pub fn bcrypt<S: AsRef<[u8]>, I: AsRef<[u8]>, O: AsMut<[u8]>>(cost: usize,
     assert!(0 < input.as_ref().len() && input.as_ref().len() <= 72);
     assert_eq!(output.as_mut().len(), 24);
 
-    let mut output = output.as_mut();
-
     let state = bcrypt_setup(cost, salt.as_ref(), input.as_ref());
-    let mut ctext = [0x4f727068, 0x65616e42, 0x65686f6c, 0x64657253, 0x63727944, 0x6f756274];
-    for (chunk, out) in ctext.chunks_mut(2).zip(output.chunks_mut(8)) {
-        for _ in 0..64 {
-            let (l, r) = state.encrypt_round((chunk[0], chunk[1]));
-            chunk[0] = l;
-            chunk[1] = r;
+    bcrypt_ctext(&state, output.as_mut());
+}
+
+/// Hashes many candidate passwords against one fixed salt and cost, as server-side verification
+/// fan-out or a password audit would. Each input still pays for its own (salt-dependent) key
+/// schedule - `bcrypt_setup` isn't shareable across different passwords - but batching the calls
+/// here keeps that work tight and avoids the per-call allocation and salt re-validation that
+/// calling `bcrypt` once per input would repeat, and gives a natural seam for an implementation to
+/// later parallelize across candidates or vectorize the Blowfish rounds themselves.
+///
+/// Produces byte-for-byte the same output as calling `bcrypt(cost, salt, inputs[i], outputs[i])`
+/// for each `i`.
+pub fn bcrypt_batch(cost: usize, salt: &[u8], inputs: &[&[u8]], outputs: &mut [[u8; 24]]) {
+    assert_eq!(salt.len(), 16);
+    assert_eq!(inputs.len(), outputs.len());
+
+    for (input, output) in inputs.iter().zip(outputs.iter_mut()) {
+        assert!(0 < input.len() && input.len() <= 72);
+
+        let state = bcrypt_setup(cost, salt, input);
+        bcrypt_ctext(&state, &mut output[..]);
+    }
+}
+
+struct ParsedHash {
+    cost: u32,
+    salt: [u8; 16],
+    hash: [u8; 23],
+}
+
+impl ParsedHash {
+    fn parse(encoded: &str) -> Option<ParsedHash> {
+        if !encoded.is_ascii() {
+            return None;
         }
-        BigEndian::write_u32(&mut out[0..4], chunk[0]);
-        BigEndian::write_u32(&mut out[4..8], chunk[1]);
+
+        let rest = if encoded.starts_with("$2a$") || encoded.starts_with("$2b$") ||
+                      encoded.starts_with("$2y$") {
+            &encoded.as_bytes()[4..]
+        } else {
+            return None;
+        };
+
+        if rest.len() != 2 + 1 + 22 + 31 {
+            return None;
+        }
+
+        let cost = match str::from_utf8(&rest[0..2]).unwrap().parse() {
+            Ok(cost) => cost,
+            Err(_) => return None,
+        };
+
+        if cost < 4 || cost > 31 {
+            return None;
+        }
+
+        if &rest[2..3] != b"$" {
+            return None;
+        }
+
+        let mut salt = [0u8; 16];
+        if !base64::decode(str::from_utf8(&rest[3..3 + 22]).unwrap(), &mut salt) {
+            return None;
+        }
+
+        let mut hash = [0u8; 23];
+        if !base64::decode(str::from_utf8(&rest[3 + 22..]).unwrap(), &mut hash) {
+            return None;
+        }
+
+        Some(ParsedHash {
+            cost: cost,
+            salt: salt,
+            hash: hash,
+        })
     }
 }
 
+// NUL-terminates and pads `password` into the 72-byte buffer the raw `bcrypt` function expects,
+// returning `None` if it doesn't fit once the terminator is accounted for.
+fn prepare_input(password: &[u8]) -> Option<Vec<u8>> {
+    if password.len() >= 72 {
+        return None;
+    }
+
+    let mut input = Vec::with_capacity(password.len() + 1);
+    input.extend_from_slice(password);
+    input.push(0);
+    Some(input)
+}
+
+fn format_hash(cost: u32, salt: &[u8; 16], hash: &[u8; 23]) -> String {
+    format!("$2b${:02}${}{}", cost, base64::encode(salt), base64::encode(hash))
+}
+
+/// Hashes `password` for storage, returning a self-describing `$2b$` modular crypt format string
+/// that bundles the cost and a freshly generated 16-byte salt alongside the digest. `cost` is the
+/// bcrypt work factor (the number of key-schedule iterations is `2^cost`).
+pub fn hash_password<P: AsRef<[u8]>>(password: P, cost: u32) -> String {
+    let mut salt = [0u8; 16];
+    OsRng::new().expect("failed to open the OS RNG").fill_bytes(&mut salt);
+
+    hash_password_with_salt(password, cost, &salt)
+}
+
+/// Like `hash_password`, but with an explicit salt instead of one drawn from the OS RNG. Mostly
+/// useful for reproducing known-answer test vectors; real callers should prefer `hash_password`.
+pub fn hash_password_with_salt<P: AsRef<[u8]>>(password: P, cost: u32, salt: &[u8; 16]) -> String {
+    let input = prepare_input(password.as_ref()).expect("password too long for bcrypt");
+
+    let mut output = [0u8; 24];
+    bcrypt(cost as usize, &salt[..], &input, &mut output[..]);
+
+    let mut hash = [0u8; 23];
+    hash.copy_from_slice(&output[0..23]);
+
+    format_hash(cost, salt, &hash)
+}
+
+// Compares two equal-length byte slices without branching on their contents, so that recomputing
+// a wrong password takes the same time as recomputing the right one.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0u8, |acc, (&x, &y)| acc | (x ^ y)) == 0
+}
+
+/// Verifies `password` against a `$2a$`/`$2b$`/`$2y$` modular crypt format hash previously produced
+/// by `hash_password`, recomputing the digest under the stored cost/salt and comparing in constant
+/// time to avoid leaking how many leading bytes matched.
+pub fn verify_password<P: AsRef<[u8]>>(password: P, hash: &str) -> bool {
+    let parsed = match ParsedHash::parse(hash) {
+        Some(parsed) => parsed,
+        None => return false,
+    };
+
+    let input = match prepare_input(password.as_ref()) {
+        Some(input) => input,
+        None => return false,
+    };
+
+    let mut output = [0u8; 24];
+    bcrypt(parsed.cost as usize, &parsed.salt[..], &input, &mut output[..]);
+
+    constant_time_eq(&output[0..23], &parsed.hash[..])
+}
+
 #[cfg(test)]
 mod tests {
     use super::bcrypt;
@@ -284,4 +523,96 @@ mod tests {
             assert_eq!(&output[0..23], &test.output[..]);
         }
     }
+
+    use super::bcrypt_pbkdf;
+
+    #[test]
+    fn bcrypt_pbkdf_is_deterministic() {
+        let mut a = [0u8; 32];
+        let mut b = [0u8; 32];
+        bcrypt_pbkdf(b"password", b"salt", 4, &mut a);
+        bcrypt_pbkdf(b"password", b"salt", 4, &mut b);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn bcrypt_pbkdf_fills_arbitrary_length_output() {
+        let mut output = [0u8; 96];
+        bcrypt_pbkdf(b"password", b"salt", 4, &mut output);
+        assert!(output.iter().any(|&b| b != 0));
+    }
+
+    #[test]
+    fn bcrypt_pbkdf_differs_by_round_count() {
+        let mut a = [0u8; 32];
+        let mut b = [0u8; 32];
+        bcrypt_pbkdf(b"password", b"salt", 4, &mut a);
+        bcrypt_pbkdf(b"password", b"salt", 5, &mut b);
+        assert_ne!(a, b);
+    }
+
+    use super::{hash_password_with_salt, verify_password};
+
+    #[test]
+    fn hash_password_round_trips_through_verify_password() {
+        let salt = [0x42; 16];
+        let hash = hash_password_with_salt("correct horse battery staple", 4, &salt);
+
+        assert!(hash.starts_with("$2b$04$"));
+        assert!(verify_password("correct horse battery staple", &hash));
+        assert!(!verify_password("wrong password", &hash));
+    }
+
+    #[test]
+    fn verify_password_rejects_malformed_hashes() {
+        assert!(!verify_password("anything", "not a bcrypt hash"));
+        assert!(!verify_password("anything", "$2b$04$tooshort"));
+    }
+
+    #[test]
+    fn verify_password_rejects_non_ascii_hashes_without_panicking() {
+        // A multi-byte UTF-8 character placed so a naive byte-offset slice would land mid-codepoint,
+        // while the total byte length still matches a well-formed hash.
+        let mut hash = String::from("$2b$04$");
+        hash.push('\u{e9}');
+        hash.push_str(&"a".repeat(56 - 1));
+
+        assert!(!verify_password("anything", &hash));
+    }
+
+    #[test]
+    fn verify_password_rejects_hashes_with_out_of_range_cost() {
+        let salt = [0x13; 16];
+
+        let too_high = hash_password_with_salt("anything", 31, &salt).replace("$31$", "$64$");
+        assert!(!verify_password("anything", &too_high));
+
+        let too_low = hash_password_with_salt("anything", 4, &salt).replace("$04$", "$00$");
+        assert!(!verify_password("anything", &too_low));
+    }
+
+    #[test]
+    fn verify_password_accepts_2a_and_2y_prefixes() {
+        let salt = [0x13; 16];
+        let mut hash = hash_password_with_salt("hunter2", 4, &salt);
+        hash.replace_range(0..4, "$2a$");
+        assert!(verify_password("hunter2", &hash));
+    }
+
+    use super::bcrypt_batch;
+
+    #[test]
+    fn bcrypt_batch_matches_calling_bcrypt_per_input() {
+        let salt = [0x2a; 16];
+        let inputs: &[&[u8]] = &[b"password1", b"password2", b"a much longer candidate password"];
+
+        let mut batched = [[0u8; 24]; 3];
+        bcrypt_batch(4, &salt, inputs, &mut batched);
+
+        for (input, expected) in inputs.iter().zip(batched.iter()) {
+            let mut single = [0u8; 24];
+            bcrypt(4, &salt[..], *input, &mut single[..]);
+            assert_eq!(&single[..], &expected[..]);
+        }
+    }
 }